@@ -24,7 +24,7 @@ fn main() {
             .include(".")
             .get_compiler()
             .to_command();
-        cmd.args(&["-omkexeloadable", "mkexeloadable.c"]);
+        cmd.args(["-omkexeloadable", "mkexeloadable.c"]);
         assert!(cmd.status().unwrap().success());
     }
 }
@@ -0,0 +1,109 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Control channel to the running app, see reloady's `control` module for
+//! the protocol.
+use std::{
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+
+#[cfg(not(target_os = "windows"))]
+use std::os::unix::net::{UnixListener as Listener, UnixStream as Stream};
+#[cfg(target_os = "windows")]
+use std::net::{TcpListener as Listener, TcpStream as Stream};
+
+// keep in sync with reloady's CONTROL_ENV
+pub const CONTROL_ENV: &str = "RELOADY_CONTROL";
+
+pub struct Control {
+    address: String,
+    // connection to the most recently started app
+    app: Arc<Mutex<Option<Stream>>>,
+}
+
+impl Control {
+    pub fn bind() -> Result<Self> {
+        let (listener, address) = listen()?;
+        let app = Arc::new(Mutex::new(None));
+        let app2 = app.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream.and_then(|s| s.try_clone().map(|r| (s, r))) {
+                    Ok((stream, reader)) => {
+                        *app2.lock().unwrap() = Some(stream);
+                        std::thread::spawn(move || read_messages(reader));
+                    }
+                    Err(e) => error!("{}", e),
+                }
+            }
+        });
+        Ok(Self { address, app })
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Tells the app that build `version` is complete and loadable.
+    pub fn announce<P: AsRef<Path>>(&self, version: usize, path: P) -> Result<()> {
+        match *self.app.lock().unwrap() {
+            Some(ref mut stream) => {
+                writeln!(stream, "ready {} {}", version, path.as_ref().to_string_lossy())?;
+                Ok(())
+            }
+            None => Err(anyhow::anyhow!(
+                "app isn't connected, build {} was not announced",
+                version
+            )),
+        }
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn listen() -> Result<(Listener, String)> {
+    let path = std::env::temp_dir().join(format!("reloady-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = Listener::bind(&path)?;
+    Ok((listener, path.to_string_lossy().into_owned()))
+}
+
+#[cfg(target_os = "windows")]
+fn listen() -> Result<(Listener, String)> {
+    let listener = Listener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?.to_string();
+    Ok((listener, address))
+}
+
+fn read_messages(reader: Stream) {
+    for line in BufReader::new(reader).lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        let mut parts = line.splitn(2, ' ');
+        match (parts.next(), parts.next()) {
+            (Some("loaded"), Some(version)) => info!("App loaded build {}", version),
+            (Some("failed"), Some(rest)) => error!("App failed to load build {}", rest),
+            (Some("restart"), Some(reason)) => info!("App asked for a restart: {}", reason),
+            _ => warn!("Unknown message from app: {:?}", line),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Fingerprints of a crate's source, used to tell whether a change can be
+//! hot patched or needs a full restart.
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, Result};
+use quote::ToTokens;
+
+#[derive(Default)]
+pub struct Fingerprint {
+    // signatures of hot-reloadable functions
+    hot_sigs: BTreeMap<String, String>,
+    // everything that is compiled into the running app for good
+    other: BTreeMap<String, String>,
+}
+
+impl Fingerprint {
+    pub fn of_dir<P: AsRef<Path>>(src_dir: P) -> Result<Self> {
+        let mut fp = Self::default();
+        fp.add_dir(src_dir.as_ref(), src_dir.as_ref())?;
+        Ok(fp)
+    }
+
+    fn add_dir(&mut self, root: &Path, dir: &Path) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                self.add_dir(root, &path)?;
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                let contents = std::fs::read_to_string(&path)?;
+                let file = syn::parse_file(&contents)
+                    .with_context(|| format!("Parse {}", path.to_string_lossy()))?;
+                let name = path.strip_prefix(root).unwrap_or(&path).to_string_lossy();
+                self.add_items(&name, &file.items, is_hot(&file.attrs, false));
+            }
+        }
+        Ok(())
+    }
+
+    // `hot` is whether the items are in a `#[hot_reload]` module
+    fn add_items(&mut self, scope: &str, items: &[syn::Item], hot: bool) {
+        for (i, item) in items.iter().enumerate() {
+            match item {
+                // state survives reloads, and migrations only run in new images
+                syn::Item::Static(s) if has_attr(&s.attrs, "hot_state") => {}
+                syn::Item::Fn(f) if has_attr(&f.attrs, "migrate") => {}
+                syn::Item::Fn(f) if f.sig.constness.is_none() && is_hot(&f.attrs, hot) => {
+                    let key = format!("{}::{}", scope, f.sig.ident);
+                    self.hot_sigs.insert(key, tokens(&f.sig));
+                }
+                syn::Item::Impl(imp) if has_hot_methods(imp, hot) => {
+                    let key = item_key(scope, i, item);
+                    let hot_impl = is_hot(&imp.attrs, hot);
+                    let mut rest = imp.clone();
+                    for item in rest.items.iter_mut() {
+                        match item {
+                            syn::ImplItem::Method(m)
+                                if m.sig.constness.is_none() && is_hot(&m.attrs, hot_impl) =>
+                            {
+                                let name = format!("{}::{}", key, m.sig.ident);
+                                self.hot_sigs.insert(name, tokens(&m.sig));
+                                // only the signature has to stay the same
+                                m.block.stmts.clear();
+                            }
+                            _ => {}
+                        }
+                    }
+                    self.other.insert(key, tokens(&rest));
+                }
+                syn::Item::Mod(syn::ItemMod {
+                    ident,
+                    content: Some((_, items)),
+                    attrs,
+                    ..
+                }) => {
+                    let scope = format!("{}::{}", scope, ident);
+                    self.other
+                        .insert(format!("{} (attributes)", scope), tokens_of(attrs));
+                    self.add_items(&scope, items, is_hot(attrs, hot));
+                }
+                _ => {
+                    self.other.insert(item_key(scope, i, item), tokens(item));
+                }
+            }
+        }
+    }
+
+    /// Why the app has to restart to pick up the changes from `self` to
+    /// `new`, or `None` if they can be hot patched.
+    pub fn restart_reason(&self, new: &Fingerprint) -> Option<String> {
+        for (name, sig) in &new.hot_sigs {
+            match self.hot_sigs.get(name) {
+                None => return Some(format!("hot function `{}` was added", name)),
+                Some(old) if old != sig => {
+                    return Some(format!("signature of hot function `{}` changed", name))
+                }
+                _ => {}
+            }
+        }
+        for (name, item) in &new.other {
+            match self.other.get(name) {
+                None => return Some(format!("`{}` was added", name)),
+                Some(old) if old != item => return Some(format!("`{}` changed", name)),
+                _ => {}
+            }
+        }
+        for name in self.other.keys() {
+            if !new.other.contains_key(name) {
+                return Some(format!("`{}` was removed", name));
+            }
+        }
+        None
+    }
+}
+
+// the impl itself, or any of its methods, is hot
+fn has_hot_methods(i: &syn::ItemImpl, hot: bool) -> bool {
+    let hot_impl = is_hot(&i.attrs, hot);
+    i.items.iter().any(|item| match item {
+        syn::ImplItem::Method(m) => is_hot(&m.attrs, hot_impl),
+        _ => false,
+    })
+}
+
+// `#[hot_reload]` makes an item hot, `#[hot_reload(skip)]` opts it out of
+// the enclosing impl or module
+fn is_hot(attrs: &[syn::Attribute], inherited: bool) -> bool {
+    let attr = attrs.iter().find(|attr| {
+        attr.path
+            .segments
+            .last()
+            .is_some_and(|s| s.ident == "hot_reload")
+    });
+    match attr {
+        Some(attr) => !attr.tokens.to_string().contains("skip"),
+        None => inherited,
+    }
+}
+
+fn has_attr(attrs: &[syn::Attribute], name: &str) -> bool {
+    attrs
+        .iter()
+        .any(|attr| attr.path.segments.last().is_some_and(|s| s.ident == name))
+}
+
+// token text ignores whitespace and comments
+fn tokens<T: ToTokens>(t: &T) -> String {
+    t.to_token_stream().to_string()
+}
+
+fn tokens_of(attrs: &[syn::Attribute]) -> String {
+    attrs.iter().map(tokens).collect::<Vec<_>>().join(" ")
+}
+
+fn item_key(scope: &str, index: usize, item: &syn::Item) -> String {
+    let ident = match item {
+        syn::Item::Const(i) => Some(&i.ident),
+        syn::Item::Enum(i) => Some(&i.ident),
+        syn::Item::Fn(i) => Some(&i.sig.ident),
+        syn::Item::Mod(i) => Some(&i.ident),
+        syn::Item::Static(i) => Some(&i.ident),
+        syn::Item::Struct(i) => Some(&i.ident),
+        syn::Item::Trait(i) => Some(&i.ident),
+        syn::Item::Type(i) => Some(&i.ident),
+        syn::Item::Union(i) => Some(&i.ident),
+        _ => None,
+    };
+    match ident {
+        Some(ident) => format!("{}::{}", scope, ident),
+        // impls, uses and macros have no name, fall back to their position
+        None => format!("{} item #{}", scope, index),
+    }
+}
@@ -20,7 +20,8 @@ use std::{
     ffi::OsString,
     io::Read,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{Child, Command, Stdio},
+    sync::mpsc::RecvTimeoutError,
     time::Duration,
 };
 
@@ -32,6 +33,15 @@ use notify::{RecursiveMode, Watcher};
 use pretty_env_logger::env_logger::Env;
 use serde_derive::{Deserialize, Serialize};
 
+mod control;
+mod fingerprint;
+
+use control::{Control, CONTROL_ENV};
+use fingerprint::Fingerprint;
+
+// keep in sync with reloady::RESTART_EXIT_CODE
+const RESTART_EXIT_CODE: i32 = 75;
+
 #[derive(Clap)]
 #[clap(
     version = "0.1",
@@ -66,6 +76,8 @@ fn main() -> Result<()> {
     reload(&cargo_toml_dir, &crate_info, false)?;
 
     let src_dir = cargo_toml_dir.join("src");
+    // what the running app was built from
+    let mut running = Fingerprint::of_dir(&src_dir)?;
     info!("Starting watcher at path {}", src_dir.to_string_lossy());
 
     let (tx, rx) = std::sync::mpsc::channel();
@@ -76,38 +88,116 @@ fn main() -> Result<()> {
 
     info!("Listening...");
 
-    let mut cmd = Command::new(get_exe_name(&cargo_toml_dir, &crate_info));
-    let mut handle = cmd.args(&args.args).spawn()?;
+    let control = Control::bind()?;
+    info!("Control channel at {}", control.address());
+    let mut build = 0;
 
-    let _th = std::thread::spawn(move || loop {
+    let mut app = Some(spawn_app(&cargo_toml_dir, &crate_info, &args, &control)?);
+
+    loop {
         use notify::DebouncedEvent::*;
-        match rx.recv() {
-            Ok(e) => match e {
-                Create(..) | Write(..) | Remove(..) | Rename(..) | Rescan => {
-                    info!("Change detected, reloading...");
-                    match reload(&cargo_toml_dir, &crate_info, true) {
-                        Ok(_) => {}
-                        Err(e) => error!("{}", e),
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(Create(..)) | Ok(Write(..)) | Ok(Remove(..)) | Ok(Rename(..)) | Ok(Rescan) => {
+                info!("Change detected, reloading...");
+                let restart_reason = match Fingerprint::of_dir(&src_dir) {
+                    Ok(new) => {
+                        let reason = running.restart_reason(&new);
+                        if reason.is_some() {
+                            running = new;
+                        }
+                        reason
+                    }
+                    // let cargo report the error
+                    Err(_) => None,
+                };
+                match (restart_reason, app.is_some()) {
+                    (Some(reason), _) => {
+                        info!("Restarting instead of hot patching: {}", reason);
+                        app = restart(app.take(), &cargo_toml_dir, &crate_info, &args, &control);
+                    }
+                    (None, false) => {
+                        info!("App is not running, starting it");
+                        app = restart(None, &cargo_toml_dir, &crate_info, &args, &control);
+                    }
+                    (None, true) => {
+                        let res = reload(&cargo_toml_dir, &crate_info, true).and_then(|_| {
+                            build += 1;
+                            control.announce(build, get_built_exe(&cargo_toml_dir, &crate_info))
+                        });
+                        if let Err(e) = res {
+                            error!("{}", e);
+                        }
                     }
                 }
-                _ => {}
-            },
+            }
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
             Err(e) => error!("{}", e),
         }
-    });
+        if let Some(ref mut child) = app {
+            if let Some(status) = child.try_wait()? {
+                if status.code() == Some(RESTART_EXIT_CODE) {
+                    info!("Restarting: app reported a change that can't be hot patched");
+                    running = Fingerprint::of_dir(&src_dir).unwrap_or(running);
+                    app = restart(None, &cargo_toml_dir, &crate_info, &args, &control);
+                } else {
+                    std::process::exit(status.code().unwrap_or_default());
+                }
+            }
+        }
+    }
+}
 
-    std::process::exit(handle.wait()?.code().unwrap_or_default());
+fn spawn_app<P: AsRef<Path>>(
+    toml_dir: P,
+    info: &CrateInfo,
+    args: &Opts,
+    control: &Control,
+) -> Result<Child> {
+    let mut cmd = Command::new(get_exe_name(toml_dir, info));
+    cmd.env(CONTROL_ENV, control.address());
+    Ok(cmd.args(&args.args).spawn()?)
 }
 
-fn get_exe_name<P: AsRef<Path>>(toml_dir: P, info: &CrateInfo) -> PathBuf {
+// kill the app if it's still running, and start a fresh build of it
+fn restart<P: AsRef<Path>>(
+    app: Option<Child>,
+    toml_dir: P,
+    info: &CrateInfo,
+    args: &Opts,
+    control: &Control,
+) -> Option<Child> {
+    if let Some(mut child) = app {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    let res = reload(&toml_dir, info, false).and_then(|_| spawn_app(&toml_dir, info, args, control));
+    match res {
+        Ok(child) => Some(child),
+        Err(e) => {
+            error!("{}", e);
+            error!("App is not running, waiting for the next change");
+            None
+        }
+    }
+}
+
+// where cargo puts the app
+fn get_built_exe<P: AsRef<Path>>(toml_dir: P, info: &CrateInfo) -> PathBuf {
     let ret = toml_dir.as_ref().join("target/debug").join(&info.name);
     #[cfg(target_os = "windows")]
+    let ret = ret.with_extension("exe");
+    ret
+}
+
+// the app to run, copied on windows so later builds can overwrite it
+fn get_exe_name<P: AsRef<Path>>(toml_dir: P, info: &CrateInfo) -> PathBuf {
+    let ret = get_built_exe(toml_dir, info);
+    #[cfg(target_os = "windows")]
     let ret = {
         use std::ffi::OsStr;
         use std::time::SystemTime;
         // Copy
-        // let path1 = PathBuf::from(get_app_path());
-        let path1 = ret.with_extension("exe");
+        let path1 = ret;
         let mut path2 = path1.file_name().unwrap().to_owned();
         let ts = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -122,12 +212,22 @@ fn get_exe_name<P: AsRef<Path>>(toml_dir: P, info: &CrateInfo) -> PathBuf {
 
 fn reload<P: AsRef<Path>>(_toml_dir: P, _info: &CrateInfo, stub: bool) -> Result<()> {
     let mut cargo_cmd = Command::new("cargo");
-    cargo_cmd.args(&["build", "--features"]);
+    cargo_cmd.args(["build", "--features"]);
     let mut features = vec!["reloady/enabled"];
     if !stub {
         features.push("reloady/unstub");
     }
     cargo_cmd.arg(features.join(","));
+    // v0 symbol names include generic args, so instantiations can be told apart
+    let mut rustflags = std::env::var("RUSTFLAGS").unwrap_or_default();
+    rustflags.push_str(" -C symbol-mangling-version=v0");
+    // images look up hot state in the app, and the app looks up hot fns in
+    // images, by their dynamic symbols. windows exports them from the objects
+    #[cfg(target_os = "linux")]
+    rustflags.push_str(" -C link-arg=-Wl,--export-dynamic");
+    #[cfg(target_os = "macos")]
+    rustflags.push_str(" -C link-arg=-Wl,-export_dynamic");
+    cargo_cmd.env("RUSTFLAGS", rustflags.trim());
     let mut cargo_inst = cargo_cmd
         .stderr(Stdio::piped())
         // .stdout(Stdio::inherit())
@@ -0,0 +1,34 @@
+fn main() {
+    reloady::init!();
+    loop {
+        println!("ping(9) = {}", ping(9));
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+}
+
+// Each of these calls the next one, until `n` runs out. Try changing any of
+// them while the app runs: hot functions always call the newest version of
+// each other, even from inside a reloaded build.
+#[reloady::hot_reload]
+fn ping(n: u32) -> u32 {
+    if n == 0 {
+        return 0;
+    }
+    1 + pong(n - 1)
+}
+
+#[reloady::hot_reload]
+fn pong(n: u32) -> u32 {
+    if n == 0 {
+        return 0;
+    }
+    1 + pang(n - 1)
+}
+
+#[reloady::hot_reload]
+fn pang(n: u32) -> u32 {
+    if n == 0 {
+        return 0;
+    }
+    1 + ping(n - 1)
+}
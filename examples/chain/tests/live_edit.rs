@@ -0,0 +1,122 @@
+//! Edits the example while `cargo hot-reload` runs it, checking that hot
+//! functions in a reloaded build reach the newest version of each other.
+//!
+//! This builds the app four times, so it's ignored by default. Run it with
+//! `cargo test -- --ignored` in this directory.
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{self, Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+// a build of the app, from a cold start
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+// the CLI, and on unix the app and builds it started, killed when the test
+// ends either way
+struct Cli(Child);
+
+impl Drop for Cli {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        let _ = Command::new("kill")
+            .arg("-9")
+            .arg(format!("-{}", self.0.id()))
+            .status();
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// a copy of the example, so editing it doesn't touch the repo
+fn copy_example(repo: &Path) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("reloady-chain-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src")).unwrap();
+    let here = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let reloady = repo
+        .join("reloady")
+        .display()
+        .to_string()
+        .replace('\\', "/");
+    let manifest = fs::read_to_string(here.join("Cargo.toml")).unwrap();
+    fs::write(
+        dir.join("Cargo.toml"),
+        manifest.replace("../../reloady", &reloady),
+    )
+    .unwrap();
+    fs::copy(here.join("src/main.rs"), dir.join("src/main.rs")).unwrap();
+    dir
+}
+
+fn edit(dir: &Path, from: &str, to: &str) {
+    let path = dir.join("src/main.rs");
+    let src = fs::read_to_string(&path).unwrap();
+    assert!(src.contains(from), "{:?} is not in main.rs", from);
+    fs::write(&path, src.replacen(from, to, 1)).unwrap();
+}
+
+fn forward<R: Read + Send + 'static>(output: R, lines: Sender<String>) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(output).lines().map_while(Result::ok) {
+            if lines.send(line).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// an app stuck in a reloaded build's wrapper stops printing, and a restart
+// would hide one
+fn wait_for(lines: &Receiver<String>, expected: &str) {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(line) if line == expected => return,
+            Ok(line) => assert!(!line.contains("Restarting"), "{}", line),
+            Err(_) => panic!("the app didn't print {:?} in time", expected),
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn edit_chain_live() {
+    let repo = Path::new(env!("CARGO_MANIFEST_DIR")).join("../..");
+    let status = Command::new("cargo")
+        .args(["build", "-p", "cargo-hot-reload", "--manifest-path"])
+        .arg(repo.join("Cargo.toml"))
+        .env_remove("CARGO_TARGET_DIR")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let dir = copy_example(&repo);
+
+    // the app shares the CLI's output
+    let mut cmd = Command::new(repo.join("target/debug/cargo-hot-reload"));
+    cmd.current_dir(&dir)
+        .env_remove("CARGO_TARGET_DIR")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    let mut cli = Cli(cmd.spawn().unwrap());
+    let (tx, lines) = mpsc::channel();
+    forward(cli.0.stdout.take().unwrap(), tx.clone());
+    forward(cli.0.stderr.take().unwrap(), tx);
+
+    // ping, pong and pang each run 3 times
+    wait_for(&lines, "ping(9) = 9");
+    edit(&dir, "1 + ping(n - 1)", "10 + ping(n - 1)");
+    wait_for(&lines, "ping(9) = 36");
+    edit(&dir, "1 + pong(n - 1)", "100 + pong(n - 1)");
+    wait_for(&lines, "ping(9) = 333");
+    edit(&dir, "1 + pang(n - 1)", "1000 + pang(n - 1)");
+    wait_for(&lines, "ping(9) = 3330");
+
+    drop(cli);
+    let _ = fs::remove_dir_all(&dir);
+}
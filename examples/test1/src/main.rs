@@ -1,27 +1,43 @@
-#![feature(link_args)]
-#![feature(linkage)]
-
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 
 fn main() {
     reloady::init!();
     loop {
-        println!("result of test = {}, test2 =", test(&NUMBER),);
+        println!(
+            "result of test = {}, calls = {}",
+            test(&NUMBER),
+            CALLS.load(Ordering::SeqCst)
+        );
         std::thread::sleep(std::time::Duration::from_millis(500));
     }
 }
 
 static NUMBER: AtomicUsize = AtomicUsize::new(0);
 
+// Hot state keeps its value across reloads, so this keeps counting
+// even after `test` is changed.
+#[reloady::hot_state]
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
 // Try changing this function to see it update in real time!
 //
-// If the signature is changed, the program will exit with an error.
+// If the signature is changed, the previous version keeps running.
 // Changing the signature of a function always requires a rebuild.
 #[reloady::hot_reload]
 fn test(au: &AtomicUsize) -> usize {
+    CALLS.fetch_add(1, Ordering::SeqCst);
     let res = au.load(Ordering::SeqCst);
     let res = res + 159;
     au.store(res, Ordering::SeqCst);
     res
 }
+
+// Runs after every reload, e.g. to clear caches built by the old code.
+#[reloady::on_reload]
+fn reloaded(reload: &reloady::Reload) {
+    println!(
+        "build {} reloaded {:?}",
+        reload.new_version, reload.functions
+    );
+}
@@ -15,8 +15,12 @@
  * You should have received a copy of the GNU Affero General Public License
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
-use quote::{format_ident, quote};
-use syn::{parse_macro_input, spanned::Spanned, FnArg, Pat, Signature};
+use quote::quote;
+#[cfg(feature = "enabled")]
+use quote::{format_ident, ToTokens};
+use syn::parse_macro_input;
+#[cfg(feature = "enabled")]
+use syn::{spanned::Spanned, FnArg, Pat, Signature};
 
 #[cfg(feature = "enabled")]
 #[proc_macro]
@@ -29,8 +33,6 @@ pub fn init(_args: proc_macro::TokenStream) -> proc_macro::TokenStream {
         proc_macro2::Span::call_site(),
     ));
     let res = quote! {
-        #[cfg_attr(not(target_os = "windows"), link_args = "-export-dynamic")]
-        extern {}
         reloady::init2(#name_lit, env!("CARGO_MANIFEST_DIR"))
     };
     res.into()
@@ -44,124 +46,1545 @@ pub fn init(_: proc_macro::TokenStream) -> proc_macro::TokenStream {
 
 #[cfg(feature = "enabled")]
 #[proc_macro_attribute]
+pub fn hot_reload(
+    args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let args = parse_macro_input!(args as FnArgs);
+    let res = match parse_macro_input!(input as syn::Item) {
+        item if args.skip => Ok(quote! { #item }),
+        syn::Item::Fn(f) if is_method(&f.sig) => hot_method(&args, f, None, None, false).map(|m| {
+            let (wrapper, companions) = (m.wrapper, m.companions);
+            quote! { #wrapper #companions }
+        }),
+        syn::Item::Fn(f) => hot_fn(&args, f),
+        syn::Item::Impl(i) => hot_impl(&args, i),
+        syn::Item::Mod(m) => hot_mod(&args, m),
+        item => Err(syn::Error::new(
+            item.span(),
+            "hot_reload only supports functions, methods, impl blocks and modules",
+        )),
+    };
+    match res {
+        Ok(output) => output.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[cfg(not(feature = "enabled"))]
+#[proc_macro_attribute]
 pub fn hot_reload(
     _args: proc_macro::TokenStream,
     input: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
-    let input = parse_macro_input!(input as syn::ItemFn);
+    input
+}
+
+// everything a hot fn's expansion needs, for free fns and methods alike
+#[cfg(feature = "enabled")]
+struct Prepared {
+    name_lit: syn::Lit,
+    // the impl's name in images, the symbol it's exported under unless it's
+    // generic
+    impl_name: proc_macro2::TokenStream,
+    // `None` if the impl can't be exported under a name
+    export_name: Option<proc_macro2::TokenStream>,
+    // the original fn, under a new name
+    new_sig: Signature,
+    block: Box<syn::Block>,
+    // the original signature with its args renamed
+    wrapped_sig: Signature,
+    // same type as the impl, so it can be called through the hot fn
+    update_sig: Signature,
+    arg_names: proc_macro2::TokenStream,
+    call: proc_macro2::TokenStream,
+    // the impl's own lifetimes are only in scope inside `update`
+    fn_ty: proc_macro2::TokenStream,
+    wrapper_fn_ty: proc_macro2::TokenStream,
+    generic_args: Vec<syn::Ident>,
+    turbofish: proc_macro2::TokenStream,
+    // const expression for the signature hash
+    sighash: proc_macro2::TokenStream,
+    // `fn` returning `sighash`, generic like the impl so that every
+    // instantiation has its own
+    sighash_sig: proc_macro2::TokenStream,
+    sig_hash_ident: syn::Ident,
+    // `fn` reporting the layouts of the types in the signature, generic
+    // like the impl
+    layouts_sig: proc_macro2::TokenStream,
+    layouts_body: proc_macro2::TokenStream,
+    layouts_ident: syn::Ident,
+}
+
+// `prefix` tells apart the companions of methods with the same name. `path`
+// goes between the module path and the impl's name in the names images
+// export it under (e.g. `Foo::` for methods of `Foo`), `None` if it isn't
+// known, and `generic` is whether the impl block is generic
+#[cfg(feature = "enabled")]
+fn prepare(
+    args: &FnArgs,
+    sig: &Signature,
+    mut block: Box<syn::Block>,
+    prefix: &str,
+    path: Option<&str>,
+    generic: bool,
+) -> syn::Result<Prepared> {
+    if let Some(ty) = impl_trait_arg(sig) {
+        return Err(syn::Error::new(
+            ty.span(),
+            "hot_reload doesn't support `impl Trait` arguments, use a type parameter instead",
+        ));
+    }
+    if let Some(constness) = sig.constness {
+        return Err(syn::Error::new(
+            constness.span(),
+            "hot_reload doesn't support const fns",
+        ));
+    }
     let new_sig = {
-        let mut s = input.sig.clone();
-        s.ident = format_ident!("__{}_fn_impl", s.ident);
+        let mut s = sig.clone();
+        s.ident = format_ident!("__{}{}_fn_impl", prefix, s.ident);
+        if s.asyncness.is_some() {
+            box_future(&mut s, &mut block, args.send);
+        }
         s
     };
-    let fn_ty = sig_as_fn_type(input.sig.clone());
-    let new_ident_lit = syn::Lit::Str(syn::LitStr::new(
-        &new_sig.ident.to_string(),
-        new_sig.ident.span(),
-    ));
-    let impl_ident = new_sig.ident.clone();
-    let lock_ident = format_ident!(
-        "__{}_FN_MUTEX",
-        input.sig.ident.to_string().to_uppercase(),
-        span = input.sig.ident.span()
-    );
-    let block = input.block;
-    let (wrapped_sig, arg_names) = transform_argnames(input.sig.clone());
-    // hash the wrapped_sig
-    let sig_hash = {
-        use std::hash::{Hash, Hasher};
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        wrapped_sig.hash(&mut hasher);
-        hasher.finish()
-    };
-    let sig_hash_lit = syn::Lit::Int(syn::LitInt::new(&sig_hash.to_string(), wrapped_sig.span()));
+    let (wrapped_sig, arg_names) = transform_argnames(sig.clone());
+    let update_sig = {
+        let (mut s, _) = transform_argnames(new_sig.clone());
+        s.ident = format_ident!("__{}{}_fn_update", prefix, sig.ident);
+        s
+    };
+    let call = match sig.asyncness {
+        Some(_) => quote! { f(#arg_names).await },
+        None => quote! { f(#arg_names) },
+    };
     let sig_hash_ident = format_ident!("{}__reloady_sighash", new_sig.ident);
-    #[cfg(target_os = "windows")]
-    let ex_str = format!(
-        "/EXPORT:{0}={0} /EXPORT:{1}={1}",
-        new_sig.ident, sig_hash_ident
-    );
-    #[cfg(not(target_os = "windows"))]
-    let ex_str = "".to_string();
+    let sighash = {
+        let lit = syn::LitInt::new(&format!("{}u64", sig_hash(&new_sig)), sig.span());
+        if args.deep {
+            let types = layout_types(&new_sig);
+            quote! { reloady::__deep_sighash(#lit, &[#(reloady::__type_layout::<#types>()),*]) }
+        } else {
+            quote! { #lit }
+        }
+    };
+    let sighash_sig = {
+        let generics = &new_sig.generics;
+        let where_clause = &new_sig.generics.where_clause;
+        quote! { fn #sig_hash_ident #generics () -> u64 #where_clause }
+    };
+    let layouts_ident = format_ident!("{}__reloady_layouts", new_sig.ident);
+    let layouts_sig = {
+        let generics = &new_sig.generics;
+        let where_clause = &new_sig.generics.where_clause;
+        quote! { fn #layouts_ident #generics (f: &mut dyn FnMut(reloady::TypeLayout)) #where_clause }
+    };
+    let layouts_body = {
+        let types = reachable_types(sig, &new_sig);
+        quote! { #(f(reloady::__layout_of!(#types));)* }
+    };
+    let generic_args = generic_args(&new_sig.generics);
+    let impl_name = match path {
+        Some(path) => {
+            let name = format!("::{}{}", path, new_sig.ident);
+            quote! { concat!(std::module_path!(), #name) }
+        }
+        // named after `Self` in symbol names
+        None => new_sig.ident.to_string().into_token_stream(),
+    };
+    // generic fns can't be exported under a name
+    let export_name = match (path, generic || !generic_args.is_empty()) {
+        (Some(_), false) => Some(impl_name.clone()),
+        _ => None,
+    };
+    let turbofish = if generic_args.is_empty() {
+        quote! {}
+    } else {
+        quote! { ::<#(#generic_args),*> }
+    };
+    Ok(Prepared {
+        name_lit: syn::Lit::Str(syn::LitStr::new(&sig.ident.to_string(), sig.ident.span())),
+        impl_name,
+        export_name,
+        fn_ty: sig_as_fn_type(new_sig.clone(), false),
+        wrapper_fn_ty: sig_as_fn_type(new_sig.clone(), true),
+        sighash,
+        sighash_sig,
+        sig_hash_ident,
+        layouts_sig,
+        layouts_body,
+        layouts_ident,
+        new_sig,
+        block,
+        wrapped_sig,
+        update_sig,
+        arg_names,
+        call,
+        generic_args,
+        turbofish,
+    })
+}
+
+// a fn images export, so the original process can find it: under
+// `export_name` followed by `suffix`, or for generic fns with rustc's
+// `linkage` (nightly only) under their mangled name. `body` is the fn's
+// statements, without braces
+#[cfg(feature = "enabled")]
+fn exported(
+    export_name: &Option<proc_macro2::TokenStream>,
+    suffix: &str,
+    sig: proc_macro2::TokenStream,
+    body: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    match export_name {
+        Some(name) => {
+            let name = quote! { concat!(#name, #suffix) };
+            quote! {
+                #[export_name = #name]
+                #[inline(never)]
+                #sig {
+                    reloady::__export!(#name);
+                    #body
+                }
+            }
+        }
+        None => quote! {
+            #[linkage = "external"]
+            #[inline(never)]
+            #sig { #body }
+        },
+    }
+}
+
+#[cfg(feature = "enabled")]
+fn hot_fn(args: &FnArgs, input: syn::ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    // generic fns are only hot with the `nightly` feature
+    if !cfg!(feature = "nightly") && !generic_args(&input.sig.generics).is_empty() {
+        return Ok(quote! { #input });
+    }
+    let Prepared {
+        name_lit,
+        impl_name,
+        export_name,
+        new_sig,
+        block,
+        wrapped_sig,
+        mut update_sig,
+        arg_names,
+        call,
+        fn_ty,
+        wrapper_fn_ty,
+        generic_args,
+        turbofish,
+        sighash,
+        sighash_sig,
+        sig_hash_ident,
+        layouts_sig,
+        layouts_body,
+        layouts_ident,
+    } = prepare(args, &input.sig, input.block, "", Some(""), false)?;
+    let (attrs, vis) = (&input.attrs, &input.vis);
+    let impl_ident = &new_sig.ident;
+    // only visible inside the wrapper
+    update_sig.ident = format_ident!("update");
+    let exports = {
+        let sighash_fn = exported(
+            &export_name,
+            "__reloady_sighash",
+            sighash_sig,
+            sighash.clone(),
+        );
+        let layouts_fn = exported(&export_name, "__reloady_layouts", layouts_sig, layouts_body);
+        let stmts = &block.stmts;
+        let impl_fn = exported(&export_name, "", quote! { #new_sig }, quote! { #(#stmts)* });
+        quote! {
+            #[allow(non_snake_case)]
+            #sighash_fn
+            #[allow(non_snake_case)]
+            #layouts_fn
+            #impl_fn
+        }
+    };
+
+    #[cfg(feature = "unstub")]
+    let registration = if generic_args.is_empty() {
+        quote! {
+            static HOT_FN: reloady::HotFn = reloady::HotFn::new(
+                #name_lit,
+                #impl_name,
+                std::module_path!(),
+                #sighash,
+                #layouts_ident,
+                #impl_ident as *const (),
+                update as *const (),
+            );
+            // register the fn before main runs, so reloady::functions() lists it
+            #[used]
+            #[cfg_attr(any(target_os = "linux", target_os = "android"), link_section = ".init_array")]
+            #[cfg_attr(target_os = "macos", link_section = "__DATA,__mod_init_func")]
+            #[cfg_attr(target_os = "windows", link_section = ".CRT$XCU")]
+            static REGISTER: extern "C" fn() = {
+                extern "C" fn register() {
+                    reloady::__register_fn(&HOT_FN);
+                }
+                register
+            };
+            fn instance() -> &'static reloady::HotFn {
+                &HOT_FN
+            }
+        }
+    } else {
+        let generics = &new_sig.generics;
+        let where_clause = &new_sig.generics.where_clause;
+        let (instance_struct, arg_strings) = instance_parts(generics, false);
+        quote! {
+            #instance_struct
+            static HOT_FN: reloady::HotGenericFn = reloady::HotGenericFn::new(
+                #name_lit,
+                #impl_name,
+                std::module_path!(),
+            );
+            fn instance #generics () -> &'static reloady::HotFn #where_clause {
+                HOT_FN.instance(
+                    std::any::type_name::<Instance<#(#generic_args),*>>(),
+                    "",
+                    || vec![#(#arg_strings),*],
+                    #sig_hash_ident #turbofish (),
+                    #layouts_ident #turbofish,
+                    #impl_ident #turbofish as *const (),
+                    update #turbofish as *const (),
+                )
+            }
+        }
+    };
 
     #[cfg(feature = "unstub")]
     let output = quote! {
-        #[cfg_attr(target_os = "windows", link_args = #ex_str)]
-        extern {}
-        #[allow(non_snake_case)]
-        #[cfg_attr(target_os = "windows", no_mangle)]
-        #[linkage = "external"]
-        #[inline(never)]
-        fn #sig_hash_ident() -> u64 { #sig_hash_lit }
-        #[cfg_attr(target_os = "windows", no_mangle)]
-        #[linkage = "external"]
-        #[inline(never)]
-        #new_sig #block
-        reloady::lazy_static! {
-            #[allow(non_upper_case_globals)]
-            static ref #lock_ident: std::sync::Mutex<#fn_ty> = std::sync::Mutex::new(#impl_ident);
-        }
-        #wrapped_sig {
-            reloady::__update_fn(#new_ident_lit, std::module_path!(), #sig_hash_lit, &#lock_ident);
-            let f = #lock_ident.lock().unwrap();
-            (*f)(#arg_names)
+        #exports
+        #(#attrs)*
+        #vis #wrapped_sig {
+            #registration
+            // called instead of the fn while a new build is pending
+            #update_sig {
+                let hot_fn = instance #turbofish ();
+                hot_fn.reload_pending();
+                // SAFETY: hot fns only ever hold fns with a matching signature hash
+                let f: #fn_ty = unsafe { std::mem::transmute(hot_fn.current()) };
+                f(#arg_names)
+            }
+            // keeps the image the call runs in loaded
+            let _call = reloady::__enter_call();
+            // SAFETY: see above
+            let f: #wrapper_fn_ty = unsafe { std::mem::transmute(instance #turbofish ().get()) };
+            #call
+        }
+    };
+    // silence unused variable warnings
+    #[cfg(not(feature = "unstub"))]
+    let _ = (name_lit, impl_name, arg_names, fn_ty);
+    // generic impls are only compiled if something refers to them
+    #[cfg(not(feature = "unstub"))]
+    let instantiate = if generic_args.is_empty() {
+        quote! {}
+    } else {
+        // SAFETY: reading locals
+        quote! {
+            unsafe { std::ptr::read_volatile(&(#sig_hash_ident #turbofish as *const ())) };
+            unsafe { std::ptr::read_volatile(&(#layouts_ident #turbofish as *const ())) };
         }
     };
+    // only runs when other hot fns of a loaded build call it
     #[cfg(not(feature = "unstub"))]
     let output = quote! {
-        #[cfg_attr(target_os = "windows", link_args = #ex_str)]
-        extern {}
-        #[allow(non_snake_case)]
-        #[cfg_attr(target_os = "windows", no_mangle)]
-        #[linkage = "external"]
-        #[inline(never)]
-        fn #sig_hash_ident() -> u64 { #sig_hash_lit }
-        #[cfg_attr(target_os = "windows", no_mangle)]
-        #[linkage = "external"]
-        #[inline(never)]
-        #new_sig #block
-        #wrapped_sig {
-            loop {}
+        #exports
+        #(#attrs)*
+        #vis #wrapped_sig {
+            #instantiate
+            // SAFETY: the original process only swaps in fns with a matching
+            // signature hash
+            let f: #wrapper_fn_ty = unsafe {
+                std::mem::transmute(reloady::__host_dispatch(#impl_ident #turbofish as *const ()))
+            };
+            #call
+        }
+    };
+
+    Ok(output)
+}
+
+// a hot method's wrapper and the associated fns it needs, which have to go in
+// an inherent impl of the same type
+#[cfg(feature = "enabled")]
+struct HotMethod {
+    wrapper: proc_macro2::TokenStream,
+    companions: proc_macro2::TokenStream,
+}
+
+// `in_trait` is the trait and the impl's items, for methods of trait impls.
+// `self_ty` is the impl's type, unless the method is annotated by itself
+#[cfg(feature = "enabled")]
+fn hot_method(
+    args: &FnArgs,
+    input: syn::ItemFn,
+    in_trait: Option<(&syn::Path, &[syn::Ident])>,
+    self_ty: Option<&syn::Type>,
+    generic_impl: bool,
+) -> syn::Result<HotMethod> {
+    let generic = generic_impl || !generic_args(&input.sig.generics).is_empty();
+    // generic methods are only hot with the `nightly` feature
+    if generic && !cfg!(feature = "nightly") {
+        return Ok(HotMethod {
+            wrapper: quote! { #input },
+            companions: quote! {},
+        });
+    }
+    let path = match self_ty {
+        _ if generic => None,
+        Some(ty) => Some(format!("{}::", compact(ty))),
+        // with `linkage`, symbols are named after the actual `Self`
+        None if cfg!(feature = "nightly") => None,
+        None => Some("Self::".to_string()),
+    };
+    let mut input = input;
+    let prefix = match in_trait {
+        Some((trait_path, items)) => {
+            // the companions live in an inherent impl, where the trait's
+            // items aren't in scope
+            let mut scope = TraitScope { trait_path, items };
+            syn::visit_mut::VisitMut::visit_signature_mut(&mut scope, &mut input.sig);
+            syn::visit_mut::VisitMut::visit_block_mut(&mut scope, &mut input.block);
+            let mut use_path = trait_path.clone();
+            use_path.segments.last_mut().unwrap().arguments = syn::PathArguments::None;
+            input.block.stmts.insert(
+                0,
+                syn::parse_quote! {
+                    #[allow(unused_imports)]
+                    use #use_path as _;
+                },
+            );
+            trait_prefix(trait_path)
+        }
+        None => String::new(),
+    };
+    let Prepared {
+        mut name_lit,
+        impl_name,
+        export_name,
+        new_sig,
+        block,
+        wrapped_sig,
+        update_sig,
+        arg_names,
+        call,
+        fn_ty,
+        wrapper_fn_ty,
+        generic_args,
+        turbofish,
+        sighash,
+        sighash_sig,
+        sig_hash_ident,
+        layouts_sig,
+        layouts_body,
+        layouts_ident,
+    } = prepare(
+        args,
+        &input.sig,
+        input.block,
+        &prefix,
+        path.as_deref(),
+        generic,
+    )?;
+    // `Trait::method`, listed as `<Type as Trait>::method`
+    if let Some((trait_path, _)) = in_trait {
+        let name = format!("{}::{}", compact(trait_path), input.sig.ident);
+        name_lit = syn::Lit::Str(syn::LitStr::new(&name, input.sig.ident.span()));
+    }
+    let (attrs, vis) = (&input.attrs, &input.vis);
+    let impl_ident = &new_sig.ident;
+    let update_ident = &update_sig.ident;
+    let hot_ident = format_ident!("__{}{}_fn_hot", prefix, input.sig.ident);
+    let generics = &new_sig.generics;
+    let where_clause = &new_sig.generics.where_clause;
+    let (instance_struct, arg_strings) = instance_parts(generics, true);
+    let exports = {
+        let sighash_fn = exported(&export_name, "__reloady_sighash", sighash_sig, sighash);
+        let layouts_fn = exported(&export_name, "__reloady_layouts", layouts_sig, layouts_body);
+        let stmts = &block.stmts;
+        let impl_fn = exported(&export_name, "", quote! { #new_sig }, quote! { #(#stmts)* });
+        quote! {
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            #sighash_fn
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            #layouts_fn
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            #impl_fn
         }
     };
 
+    #[cfg(feature = "unstub")]
+    let output = HotMethod {
+        wrapper: quote! {
+            #(#attrs)*
+            #vis #wrapped_sig {
+                // keeps the image the call runs in loaded
+                let _call = reloady::__enter_call();
+                // SAFETY: hot fns only ever hold fns with a matching signature hash
+                let f: #wrapper_fn_ty = unsafe { std::mem::transmute(Self::#hot_ident #turbofish ().get()) };
+                #call
+            }
+        },
+        companions: quote! {
+            #exports
+            // called instead of the method while a new build is pending
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            #update_sig {
+                let hot_fn = Self::#hot_ident #turbofish ();
+                hot_fn.reload_pending();
+                // SAFETY: see the wrapper
+                let f: #fn_ty = unsafe { std::mem::transmute(hot_fn.current()) };
+                f(#arg_names)
+            }
+            // methods are registered on their first call, since their
+            // symbols are named after `Self`
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            fn #hot_ident #generics () -> &'static reloady::HotFn #where_clause {
+                #instance_struct
+                static HOT_FN: reloady::HotGenericFn = reloady::HotGenericFn::new(
+                    #name_lit,
+                    #impl_name,
+                    std::module_path!(),
+                );
+                HOT_FN.instance(
+                    std::any::type_name::<Instance<Self, #(#generic_args),*>>(),
+                    std::any::type_name::<Self>(),
+                    || vec![#(#arg_strings),*],
+                    Self::#sig_hash_ident #turbofish (),
+                    Self::#layouts_ident #turbofish,
+                    Self::#impl_ident #turbofish as *const (),
+                    Self::#update_ident #turbofish as *const (),
+                )
+            }
+        },
+    };
+    #[cfg(not(feature = "unstub"))]
+    let output = {
+        // silence unused variable warnings
+        let _ = (name_lit, impl_name, update_ident, fn_ty);
+        let _ = (
+            arg_names,
+            hot_ident,
+            where_clause,
+            instance_struct,
+            arg_strings,
+        );
+        let _ = generic_args;
+        HotMethod {
+            // only runs when other hot fns of a loaded build call it
+            wrapper: quote! {
+                #(#attrs)*
+                #vis #wrapped_sig {
+                    // generic impls are only compiled if something refers to them
+                    // SAFETY: reading locals
+                    unsafe { std::ptr::read_volatile(&(Self::#sig_hash_ident #turbofish as *const ())) };
+                    unsafe { std::ptr::read_volatile(&(Self::#layouts_ident #turbofish as *const ())) };
+                    // SAFETY: the original process only swaps in fns with a
+                    // matching signature hash
+                    let f: #wrapper_fn_ty = unsafe {
+                        std::mem::transmute(reloady::__host_dispatch(Self::#impl_ident #turbofish as *const ()))
+                    };
+                    #call
+                }
+            },
+            companions: exports,
+        }
+    };
+
+    Ok(output)
+}
+
+// every method of an impl block, the companions of trait methods go in an
+// inherent impl next to it
+#[cfg(feature = "enabled")]
+fn hot_impl(args: &FnArgs, mut input: syn::ItemImpl) -> syn::Result<proc_macro2::TokenStream> {
+    let items: Vec<syn::Ident> = input
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            syn::ImplItem::Const(c) => Some(c.ident.clone()),
+            syn::ImplItem::Method(m) => Some(m.sig.ident.clone()),
+            syn::ImplItem::Type(t) => Some(t.ident.clone()),
+            _ => None,
+        })
+        .collect();
+    let trait_path = input.trait_.as_ref().map(|(_, path, _)| path.clone());
+    let generic_impl = !generic_args(&input.generics).is_empty();
+    let mut companions = Vec::new();
+    let mut new_items = Vec::new();
+    for item in std::mem::take(&mut input.items) {
+        match item {
+            // const fns can't be swapped, leave them alone
+            syn::ImplItem::Method(mut m) if m.sig.constness.is_none() => {
+                // methods can have their own options, or opt out
+                let own_args = take_hot_attr(&mut m.attrs)?;
+                if own_args.as_ref().is_some_and(|a| a.skip) {
+                    new_items.push(syn::ImplItem::Method(m));
+                    continue;
+                }
+                let f = syn::ItemFn {
+                    attrs: m.attrs,
+                    vis: m.vis,
+                    sig: m.sig,
+                    block: Box::new(m.block),
+                };
+                let in_trait = trait_path.as_ref().map(|path| (path, &items[..]));
+                let method = hot_method(
+                    own_args.as_ref().unwrap_or(args),
+                    f,
+                    in_trait,
+                    Some(&input.self_ty),
+                    generic_impl,
+                )
+                .map_err(|e| with_skip_hint(e, own_args.is_none()))?;
+                new_items.push(syn::parse2(method.wrapper)?);
+                companions.push(method.companions);
+            }
+            item => new_items.push(item),
+        }
+    }
+    input.items = new_items;
+    match trait_path {
+        None => {
+            for companion in companions {
+                let items: syn::ItemImpl = syn::parse2(quote! { impl X { #companion } })?;
+                input.items.extend(items.items);
+            }
+            Ok(quote! { #input })
+        }
+        Some(_) => {
+            let (impl_generics, _, where_clause) = input.generics.split_for_impl();
+            let self_ty = &input.self_ty;
+            Ok(quote! {
+                #input
+                impl #impl_generics #self_ty #where_clause {
+                    #(#companions)*
+                }
+            })
+        }
+    }
+}
+
+// every free fn, method and impl block of a module, and of the modules in it
+#[cfg(feature = "enabled")]
+fn hot_mod(args: &FnArgs, input: syn::ItemMod) -> syn::Result<proc_macro2::TokenStream> {
+    let items = match input.content {
+        Some((_, items)) => items,
+        None => {
+            return Err(syn::Error::new(
+                input.span(),
+                "hot_reload can't see into `mod` declarations, put `#![reloady::hot_reload]` at the top of the module's file instead",
+            ))
+        }
+    };
+    let mut new_items = Vec::new();
+    for mut item in items {
+        let own_args = match item {
+            syn::Item::Fn(syn::ItemFn { ref mut attrs, .. })
+            | syn::Item::Impl(syn::ItemImpl { ref mut attrs, .. })
+            | syn::Item::Mod(syn::ItemMod { ref mut attrs, .. }) => take_hot_attr(attrs)?,
+            _ => None,
+        };
+        let item_args = own_args.as_ref().unwrap_or(args);
+        let output = match item {
+            _ if item_args.skip => quote! { #item },
+            // const fns can't be swapped, and migrations only run in new images
+            syn::Item::Fn(f) if f.sig.constness.is_some() || has_attr(&f.attrs, "migrate") => {
+                quote! { #f }
+            }
+            syn::Item::Fn(f) => {
+                hot_fn(item_args, f).map_err(|e| with_skip_hint(e, own_args.is_none()))?
+            }
+            syn::Item::Impl(i) => hot_impl(item_args, i)?,
+            syn::Item::Mod(m) => hot_mod(item_args, m)?,
+            // anything else, like macro invocations and trait definitions,
+            // stays as it is
+            item => quote! { #item },
+        };
+        new_items.push(output);
+    }
+    let (inner_attrs, attrs): (Vec<_>, Vec<_>) = input
+        .attrs
+        .iter()
+        .partition(|attr| matches!(attr.style, syn::AttrStyle::Inner(_)));
+    let (vis, mod_token, ident) = (&input.vis, &input.mod_token, &input.ident);
+    Ok(quote! {
+        #(#attrs)*
+        #vis #mod_token #ident {
+            #(#inner_attrs)*
+            #(#new_items)*
+        }
+    })
+}
+
+// takes `#[hot_reload(..)]` off an item inside a hot impl or module, with its
+// options
+#[cfg(feature = "enabled")]
+fn take_hot_attr(attrs: &mut Vec<syn::Attribute>) -> syn::Result<Option<FnArgs>> {
+    let pos = match attrs.iter().position(|attr| {
+        attr.path
+            .segments
+            .last()
+            .is_some_and(|s| s.ident == "hot_reload")
+    }) {
+        Some(pos) => pos,
+        None => return Ok(None),
+    };
+    let attr = attrs.remove(pos);
+    if attr.tokens.is_empty() {
+        syn::parse2(quote! {}).map(Some)
+    } else {
+        attr.parse_args().map(Some)
+    }
+}
+
+#[cfg(feature = "enabled")]
+fn has_attr(attrs: &[syn::Attribute], name: &str) -> bool {
+    attrs
+        .iter()
+        .any(|attr| attr.path.segments.last().is_some_and(|s| s.ident == name))
+}
+
+// items that were only made hot by an enclosing impl or module can opt out
+#[cfg(feature = "enabled")]
+fn with_skip_hint(mut e: syn::Error, inherited: bool) -> syn::Error {
+    if inherited {
+        e.combine(syn::Error::new(
+            e.span(),
+            "add `#[reloady::hot_reload(skip)]` to leave it out of hot reloading",
+        ));
+    }
+    e
+}
+
+// a receiver, or `Self` anywhere in the signature
+#[cfg(feature = "enabled")]
+fn is_method(sig: &Signature) -> bool {
+    sig.receiver().is_some()
+        || quote!(#sig)
+            .into_iter()
+            .any(|t| matches!(t, proc_macro2::TokenTree::Ident(ref i) if i == "Self"))
+}
+
+// token text without the spaces quote puts between every token
+#[cfg(feature = "enabled")]
+fn compact<T: ToTokens>(t: &T) -> String {
+    let mut out = String::new();
+    for token in t.to_token_stream().to_string().split(' ') {
+        let word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        if word(out.chars().last()) && word(token.chars().next()) {
+            out.push(' ');
+        }
+        out.push_str(token);
+    }
+    out
+}
+
+// e.g. `System_` for `impl System for Foo`, with a hash if the trait has
+// generic args, since `Foo` can implement `From<A>` and `From<B>`
+#[cfg(feature = "enabled")]
+fn trait_prefix(trait_path: &syn::Path) -> String {
+    let last = trait_path.segments.last().unwrap();
+    match last.arguments {
+        syn::PathArguments::None => format!("{}_", last.ident),
+        ref args => {
+            use std::hash::{Hash, Hasher};
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            quote!(#args).to_string().hash(&mut hasher);
+            format!("{}_{:x}_", last.ident, hasher.finish() as u32)
+        }
+    }
+}
+
+// rewrites `Self::Item` to `<Self as Trait>::Item` for the trait impl's items
+#[cfg(feature = "enabled")]
+struct TraitScope<'a> {
+    trait_path: &'a syn::Path,
+    items: &'a [syn::Ident],
+}
+
+#[cfg(feature = "enabled")]
+impl TraitScope<'_> {
+    fn qualify(&self, qself: &mut Option<syn::QSelf>, path: &mut syn::Path) {
+        let is_item = qself.is_none()
+            && path.leading_colon.is_none()
+            && path.segments.len() >= 2
+            && path.segments[0].ident == "Self"
+            && self.items.contains(&path.segments[1].ident);
+        if !is_item {
+            return;
+        }
+        let trait_path = self.trait_path;
+        let rest = path.segments.iter().skip(1);
+        let new_path: syn::Path = syn::parse_quote! { #trait_path::#(#rest)::* };
+        *qself = Some(syn::QSelf {
+            lt_token: Default::default(),
+            ty: Box::new(syn::parse_quote! { Self }),
+            position: trait_path.segments.len(),
+            as_token: Some(Default::default()),
+            gt_token: Default::default(),
+        });
+        *path = new_path;
+    }
+}
+
+#[cfg(feature = "enabled")]
+impl syn::visit_mut::VisitMut for TraitScope<'_> {
+    fn visit_type_path_mut(&mut self, ty: &mut syn::TypePath) {
+        self.qualify(&mut ty.qself, &mut ty.path);
+        syn::visit_mut::visit_type_path_mut(self, ty);
+    }
+
+    fn visit_expr_path_mut(&mut self, expr: &mut syn::ExprPath) {
+        self.qualify(&mut expr.qself, &mut expr.path);
+        syn::visit_mut::visit_expr_path_mut(self, expr);
+    }
+
+    // items inside the body have their own `Self`
+    fn visit_item_mut(&mut self, _: &mut syn::Item) {}
+}
+
+// the struct whose type name tells instantiations apart, and the generic
+// args as they appear in symbol names
+#[cfg(feature = "enabled")]
+fn instance_parts(
+    generics: &syn::Generics,
+    method: bool,
+) -> (proc_macro2::TokenStream, Vec<proc_macro2::TokenStream>) {
+    let mut params = Vec::new();
+    let mut phantom = Vec::new();
+    if method {
+        params.push(quote! { Self_: ?Sized });
+        phantom.push(quote! { Self_ });
+    }
+    for p in &generics.params {
+        match p {
+            syn::GenericParam::Type(t) => {
+                let ident = &t.ident;
+                params.push(quote! { #ident: ?Sized });
+                phantom.push(quote! { #ident });
+            }
+            syn::GenericParam::Const(c) => {
+                let (ident, ty) = (&c.ident, &c.ty);
+                params.push(quote! { const #ident: #ty });
+            }
+            syn::GenericParam::Lifetime(_) => {}
+        }
+    }
+    let instance_struct = quote! {
+        // its type name is unique to each instantiation
+        #[allow(dead_code)]
+        struct Instance<#(#params),*>(std::marker::PhantomData<(#(*const #phantom,)*)>);
+    };
+    let arg_strings = generics
+        .params
+        .iter()
+        .filter_map(|p| match p {
+            syn::GenericParam::Type(t) => {
+                let ident = &t.ident;
+                Some(quote! { std::any::type_name::<#ident>().to_string() })
+            }
+            syn::GenericParam::Const(c) => {
+                let ident = &c.ident;
+                Some(quote! { #ident.to_string() })
+            }
+            syn::GenericParam::Lifetime(_) => None,
+        })
+        .collect();
+    (instance_struct, arg_strings)
+}
+
+#[cfg(feature = "enabled")]
+#[proc_macro_attribute]
+pub fn hot_state(
+    args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let args = parse_macro_input!(args as StateArgs);
+    let input = parse_macro_input!(input as syn::ItemStatic);
+    if let Some(mutability) = input.mutability {
+        return syn::Error::new(
+            mutability.span(),
+            "hot_state does not support `static mut`, use interior mutability instead",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let syn::ItemStatic {
+        attrs,
+        vis,
+        ident,
+        ty,
+        expr,
+        ..
+    } = input;
+    let ident_lit = syn::Lit::Str(syn::LitStr::new(&ident.to_string(), ident.span()));
+    let migration = match args.migrate {
+        Some(mut path) => {
+            let last = path.segments.last_mut().unwrap();
+            last.ident = format_ident!("__reloady_migration_{}", last.ident);
+            quote! { Some(#path) }
+        }
+        None => quote! { None },
+    };
+    // every image exports the static under the same name, so reloaded
+    // images can find the original process's copy
+    let output = quote! {
+        reloady::__export!(concat!("__reloady_state::", module_path!(), "::", #ident_lit));
+        #(#attrs)*
+        #[export_name = concat!("__reloady_state::", module_path!(), "::", #ident_lit)]
+        #vis static #ident: reloady::HotState<#ty> = reloady::HotState::with_migration(
+            concat!("__reloady_state::", module_path!(), "::", #ident_lit),
+            || #expr,
+            #migration,
+        );
+    };
+
     output.into()
 }
 
 #[cfg(not(feature = "enabled"))]
 #[proc_macro_attribute]
-pub fn hot_reload(
+pub fn hot_state(
     _args: proc_macro::TokenStream,
     input: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
     input
 }
 
+// `#[hot_state(migrate = path::to::fn)]`
+#[cfg(feature = "enabled")]
+struct StateArgs {
+    migrate: Option<syn::Path>,
+}
+
+#[cfg(feature = "enabled")]
+impl syn::parse::Parse for StateArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut migrate = None;
+        while !input.is_empty() {
+            let key: syn::Ident = input.parse()?;
+            input.parse::<syn::Token![=]>()?;
+            if key == "migrate" {
+                migrate = Some(input.parse()?);
+            } else {
+                return Err(syn::Error::new(key.span(), "unknown hot_state option"));
+            }
+            if !input.is_empty() {
+                input.parse::<syn::Token![,]>()?;
+            }
+        }
+        Ok(Self { migrate })
+    }
+}
+
+#[cfg(feature = "enabled")]
+#[proc_macro_attribute]
+pub fn migrate(
+    _args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as syn::ItemFn);
+    let old_ty = match (input.sig.inputs.len(), input.sig.inputs.first()) {
+        (1, Some(FnArg::Typed(arg))) => arg.ty.clone(),
+        _ => {
+            return syn::Error::new(
+                input.sig.inputs.span(),
+                "migrations take exactly one argument, the old value",
+            )
+            .to_compile_error()
+            .into()
+        }
+    };
+    let new_ty = match input.sig.output {
+        syn::ReturnType::Type(_, ref ty) => ty.clone(),
+        syn::ReturnType::Default => {
+            return syn::Error::new(input.sig.span(), "migrations must return the new value")
+                .to_compile_error()
+                .into()
+        }
+    };
+    let ident = &input.sig.ident;
+    let vis = &input.vis;
+    let migration_ident = format_ident!("__reloady_migration_{}", ident);
+    let output = quote! {
+        #input
+        #[doc(hidden)]
+        #[allow(non_upper_case_globals)]
+        #vis const #migration_ident: reloady::Migration<#new_ty> = reloady::Migration::new(
+            reloady::__layout_hash::<#old_ty>,
+            // SAFETY: only called when the stored layout matches the old type
+            |old| #ident(unsafe { reloady::__take_state::<#old_ty>(old) }),
+        );
+    };
+
+    output.into()
+}
+
+#[cfg(not(feature = "enabled"))]
+#[proc_macro_attribute]
+pub fn migrate(
+    _args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as syn::ItemFn);
+    // nothing refers to migrations when hot reloading is off
+    (quote! {
+        #[allow(dead_code)]
+        #input
+    })
+    .into()
+}
+
+// `#[on_reload]` runs the fn after each reload, `#[on_reload(before)]`
+// before it
+#[cfg(feature = "enabled")]
+#[proc_macro_attribute]
+pub fn on_reload(
+    args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let when = parse_macro_input!(args as Option<syn::Ident>);
+    let input = parse_macro_input!(input as syn::ItemFn);
+    let register = match when {
+        Some(ref when) if when == "before" => quote! { on_before_reload },
+        Some(ref when) if when != "after" => {
+            return syn::Error::new(when.span(), "expected `before` or `after`")
+                .to_compile_error()
+                .into()
+        }
+        _ => quote! { on_after_reload },
+    };
+    let ident = &input.sig.ident;
+    // only the original process runs callbacks
+    #[cfg(feature = "unstub")]
+    let output = quote! {
+        #input
+        const _: () = {
+            // registered before main runs, like hot fns
+            #[used]
+            #[cfg_attr(any(target_os = "linux", target_os = "android"), link_section = ".init_array")]
+            #[cfg_attr(target_os = "macos", link_section = "__DATA,__mod_init_func")]
+            #[cfg_attr(target_os = "windows", link_section = ".CRT$XCU")]
+            static REGISTER: extern "C" fn() = {
+                extern "C" fn register() {
+                    reloady::#register(#ident);
+                }
+                register
+            };
+        };
+    };
+    #[cfg(not(feature = "unstub"))]
+    let output = {
+        let _ = (register, ident);
+        quote! {
+            #[allow(dead_code)]
+            #input
+        }
+    };
+
+    output.into()
+}
+
+#[cfg(not(feature = "enabled"))]
+#[proc_macro_attribute]
+pub fn on_reload(
+    _args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as syn::ItemFn);
+    // nothing calls callbacks when hot reloading is off
+    (quote! {
+        #[allow(dead_code)]
+        #input
+    })
+    .into()
+}
+
+// fingerprints field names, offsets and layouts. also derived when hot
+// reloading is off, since the trait always exists
+#[proc_macro_derive(Layout)]
+pub fn derive_layout(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as syn::DeriveInput);
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let (kind, body) = match input.data {
+        syn::Data::Struct(ref s) => ("struct", field_offsets(s.fields.iter())),
+        syn::Data::Union(ref u) => ("union", field_offsets(u.fields.named.iter())),
+        // variants have no offsets that could be taken from outside
+        syn::Data::Enum(ref e) => {
+            let variants = e.variants.iter().map(|v| {
+                let name = v.ident.to_string();
+                let fields = v.fields.iter().enumerate().map(|(i, f)| {
+                    let (name, ty) = (field_name(i, f), &f.ty);
+                    quote! { hasher.field(#name, 0, reloady::__layout_of!(#ty)); }
+                });
+                quote! {
+                    hasher.variant(#name);
+                    #(#fields)*
+                }
+            });
+            ("enum", quote! { #(#variants)* })
+        }
+    };
+    (quote! {
+        impl #impl_generics reloady::Layout for #ident #ty_generics #where_clause {
+            const KIND: &'static str = #kind;
+            fn fields() -> u64 {
+                let mut hasher = reloady::__FieldHasher::new();
+                #body
+                hasher.finish()
+            }
+        }
+    })
+    .into()
+}
+
+fn field_offsets<'a>(fields: impl Iterator<Item = &'a syn::Field>) -> proc_macro2::TokenStream {
+    let fields: Vec<_> = fields
+        .enumerate()
+        .map(|(i, f)| {
+            let (name, ty) = (field_name(i, f), &f.ty);
+            let member = match f.ident {
+                Some(ref ident) => syn::Member::Named(ident.clone()),
+                None => syn::Member::Unnamed(i.into()),
+            };
+            quote! {
+                // SAFETY: only takes the field's address, never reads it
+                let offset = unsafe { std::ptr::addr_of!((*base).#member) } as usize - base as usize;
+                hasher.field(#name, offset, reloady::__layout_of!(#ty));
+            }
+        })
+        .collect();
+    if fields.is_empty() {
+        return quote! {};
+    }
+    quote! {
+        let uninit = std::mem::MaybeUninit::<Self>::uninit();
+        let base = uninit.as_ptr();
+        #(#fields)*
+    }
+}
+
+fn field_name(index: usize, field: &syn::Field) -> String {
+    match field.ident {
+        Some(ref ident) => ident.to_string(),
+        None => index.to_string(),
+    }
+}
+
+// `#[hot_reload(?Send)]`
+#[cfg(feature = "enabled")]
+struct FnArgs {
+    // whether futures returned by async fns are Send
+    send: bool,
+    // leave the item alone, for opting out inside hot modules and impls
+    skip: bool,
+    // also hash the layouts of argument and return types
+    deep: bool,
+}
+
+#[cfg(feature = "enabled")]
+impl syn::parse::Parse for FnArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut send = true;
+        let mut skip = false;
+        let mut deep = false;
+        while !input.is_empty() {
+            if input.peek(syn::Token![?]) {
+                input.parse::<syn::Token![?]>()?;
+                let ident: syn::Ident = input.parse()?;
+                if ident != "Send" {
+                    return Err(syn::Error::new(ident.span(), "expected `?Send`"));
+                }
+                send = false;
+            } else if input.peek(syn::Ident) {
+                let ident: syn::Ident = input.parse()?;
+                match ident.to_string().as_str() {
+                    "skip" => skip = true,
+                    "deep" => deep = true,
+                    _ => return Err(syn::Error::new(ident.span(), "unknown hot_reload option")),
+                }
+            } else {
+                return Err(input.error("unknown hot_reload option"));
+            }
+            if !input.is_empty() {
+                input.parse::<syn::Token![,]>()?;
+            }
+        }
+        Ok(Self { send, skip, deep })
+    }
+}
+
+// turn `async fn f(..) -> T` into `fn f(..) -> Pin<Box<dyn Future<Output = T>>>`,
+// so the future has the same type in every build.
+//
+// fn pointers can't have lifetime bounds, so every lifetime in the signature
+// becomes `'__reloady`, which the future lives for (or `'static` if no args
+// borrow anything).
+#[cfg(feature = "enabled")]
+fn box_future(sig: &mut Signature, block: &mut Box<syn::Block>, send: bool) {
+    use syn::visit_mut::VisitMut;
+
+    struct Unify {
+        lifetime: syn::Lifetime,
+        // the fn's own lifetime params
+        params: Vec<syn::Lifetime>,
+        // name elided lifetimes too, only in the signature
+        elided: bool,
+        // whether any lifetime was unified
+        used: bool,
+    }
+
+    impl VisitMut for Unify {
+        fn visit_lifetime_mut(&mut self, lt: &mut syn::Lifetime) {
+            if (self.elided && lt.ident == "_") || self.params.contains(lt) {
+                *lt = self.lifetime.clone();
+                self.used = true;
+            }
+        }
+
+        fn visit_type_reference_mut(&mut self, r: &mut syn::TypeReference) {
+            if self.elided && r.lifetime.is_none() {
+                r.lifetime = Some(self.lifetime.clone());
+                self.used = true;
+            }
+            syn::visit_mut::visit_type_reference_mut(self, r);
+        }
+
+        fn visit_receiver_mut(&mut self, r: &mut syn::Receiver) {
+            if let Some((_, ref mut lt @ None)) = r.reference {
+                *lt = Some(self.lifetime.clone());
+                self.used = true;
+            }
+            syn::visit_mut::visit_receiver_mut(self, r);
+        }
+    }
+
+    let lifetime = syn::Lifetime::new("'__reloady", proc_macro2::Span::call_site());
+    let params = sig
+        .generics
+        .lifetimes()
+        .map(|def| def.lifetime.clone())
+        .collect();
+    let mut unify = Unify {
+        lifetime: lifetime.clone(),
+        params,
+        elided: true,
+        used: false,
+    };
+    for arg in sig.inputs.iter_mut() {
+        unify.visit_fn_arg_mut(arg);
+    }
+    // without borrowed args, the future can't borrow anything
+    let borrows = unify.used;
+    if !borrows {
+        unify.lifetime = syn::Lifetime::new("'static", proc_macro2::Span::call_site());
+    }
+    unify.visit_return_type_mut(&mut sig.output);
+    if let Some(ref mut where_clause) = sig.generics.where_clause {
+        unify.visit_where_clause_mut(where_clause);
+    }
+    unify.elided = false;
+    unify.visit_block_mut(block);
+
+    let lifetime = unify.lifetime;
+    sig.generics.params = std::iter::once(syn::GenericParam::Lifetime(syn::LifetimeDef::new(
+        lifetime.clone(),
+    )))
+    .filter(|_| borrows)
+    .chain(
+        sig.generics
+            .params
+            .iter()
+            .filter(|p| !matches!(p, syn::GenericParam::Lifetime(_)))
+            .cloned(),
+    )
+    .collect();
+    // the future owns the fn's generic args
+    let type_params: Vec<_> = sig
+        .generics
+        .type_params()
+        .map(|t| t.ident.clone())
+        .collect();
+    let where_clause = sig.generics.make_where_clause();
+    for ident in type_params {
+        where_clause
+            .predicates
+            .push(syn::parse_quote! { #ident: #lifetime });
+    }
+
+    let output = match sig.output {
+        syn::ReturnType::Default => quote! { () },
+        syn::ReturnType::Type(_, ref ty) => quote! { #ty },
+    };
+    let send = if send {
+        quote! { + Send }
+    } else {
+        quote! {}
+    };
+    sig.asyncness = None;
+    sig.output = syn::parse_quote! {
+        -> std::pin::Pin<std::boxed::Box<dyn std::future::Future<Output = #output> #send + #lifetime>>
+    };
+    *block = syn::parse_quote! {
+        { std::boxed::Box::pin(async move #block) }
+    };
+}
+
+#[cfg(feature = "enabled")]
 fn transform_argnames(mut sig: Signature) -> (Signature, proc_macro2::TokenStream) {
-    let arg_names: Vec<syn::Ident> = (0..sig.inputs.len())
-        .map(|x| format_ident!("_arg{}", x))
+    let arg_names: Vec<proc_macro2::TokenStream> = sig
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| match arg {
+            FnArg::Receiver(_) => quote! { self },
+            FnArg::Typed(typed) if is_self(&typed.pat) => quote! { self },
+            FnArg::Typed(_) => format_ident!("_arg{}", i).into_token_stream(),
+        })
         .collect();
+    // the wrapper takes whole values and leaves destructuring to the impl
     for (i, arg) in sig.inputs.iter_mut().enumerate() {
-        match arg {
-            FnArg::Typed(typed) => match *typed.pat {
-                Pat::Ident(ref mut ident) => {
-                    ident.ident = arg_names[i].clone();
-                }
-                _ => {}
-            },
-            _ => {}
+        if let FnArg::Typed(typed) = arg {
+            if !is_self(&typed.pat) {
+                let ident = format_ident!("_arg{}", i);
+                *typed.pat = syn::parse_quote! { #ident };
+            }
         }
     }
     (sig, quote! { #(#arg_names),* })
 }
 
-fn sig_as_fn_type(sig: Signature) -> proc_macro2::TokenStream {
-    let (generic_params, where_clause) = (sig.generics.params, sig.generics.where_clause);
-    let (sig_args, sig_async, sig_abi, sig_unsafe, sig_ret) =
-        (sig.inputs, sig.asyncness, sig.abi, sig.unsafety, sig.output);
-    let ret = quote! {
-        for<#generic_params> #sig_unsafe #sig_abi #sig_async fn(#sig_args) #sig_ret #where_clause
+// `self: Box<Self>` and friends
+#[cfg(feature = "enabled")]
+fn is_self(pat: &Pat) -> bool {
+    matches!(pat, Pat::Ident(ident) if ident.ident == "self")
+}
+
+// `late` turns the lifetimes that only the impl has (e.g. async fns'
+// `'__reloady`) into higher-ranked ones, for use outside the impl
+#[cfg(feature = "enabled")]
+fn sig_as_fn_type(sig: Signature, late: bool) -> proc_macro2::TokenStream {
+    let lifetimes: Vec<_> = sig
+        .generics
+        .lifetimes()
+        .map(|def| &def.lifetime)
+        .filter(|lt| late && lt.ident == "__reloady")
+        .collect();
+    // fn pointer types only take the args' types
+    let sig_args = sig.inputs.iter().map(|arg| match arg {
+        FnArg::Receiver(syn::Receiver {
+            reference: Some((_, lt)),
+            mutability,
+            ..
+        }) => quote! { &#lt #mutability Self },
+        FnArg::Receiver(_) => quote! { Self },
+        FnArg::Typed(typed) => typed.ty.to_token_stream(),
+    });
+    let (sig_abi, sig_unsafe, sig_ret) = (&sig.abi, &sig.unsafety, &sig.output);
+    quote! {
+        for<#(#lifetimes),*> #sig_unsafe #sig_abi fn(#(#sig_args),*) #sig_ret
+    }
+}
+
+// FNV-1a over the signature as the impl's fn pointer type, with its generics.
+// unlike DefaultHasher, this is the same for every compiler and platform, and
+// arg names, spans and formatting don't change it
+#[cfg(feature = "enabled")]
+fn sig_hash(sig: &Signature) -> u64 {
+    let generics = &sig.generics;
+    let where_clause = &sig.generics.where_clause;
+    let mut text = String::from("reloady sighash v1");
+    normalize(sig_as_fn_type(sig.clone(), false), &mut text);
+    normalize(quote! { #generics #where_clause }, &mut text);
+    let mut hash = 0xcbf29ce484222325u64;
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// one space between tokens, and nothing else that depends on how the
+// tokens were written
+#[cfg(feature = "enabled")]
+fn normalize(tokens: proc_macro2::TokenStream, out: &mut String) {
+    use proc_macro2::{Delimiter, TokenTree};
+    for token in tokens {
+        out.push(' ');
+        match token {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::None => ("", ""),
+                };
+                out.push_str(open);
+                normalize(group.stream(), out);
+                out.push(' ');
+                out.push_str(close);
+            }
+            TokenTree::Ident(ident) => out.push_str(&ident.to_string()),
+            TokenTree::Punct(punct) => out.push(punct.as_char()),
+            TokenTree::Literal(lit) => out.push_str(&lit.to_string()),
+        }
+    }
+}
+
+// argument and return types, for `deep` signature hashes. lifetimes don't
+// change layouts, and aren't in scope where the hash is computed
+#[cfg(feature = "enabled")]
+fn layout_types(sig: &Signature) -> Vec<syn::Type> {
+    use syn::visit_mut::VisitMut;
+
+    struct Static;
+
+    impl VisitMut for Static {
+        fn visit_lifetime_mut(&mut self, lt: &mut syn::Lifetime) {
+            *lt = syn::Lifetime::new("'static", lt.span());
+        }
+    }
+
+    let args = sig.inputs.iter().map(|arg| match arg {
+        FnArg::Receiver(syn::Receiver {
+            reference: Some((_, _)),
+            mutability,
+            ..
+        }) => syn::parse_quote! { &#mutability Self },
+        FnArg::Receiver(_) => syn::parse_quote! { Self },
+        FnArg::Typed(typed) => (*typed.ty).clone(),
+    });
+    let ret = match sig.output {
+        // `!` has no layout
+        syn::ReturnType::Type(_, ref ty) if !matches!(**ty, syn::Type::Never(_)) => {
+            Some((**ty).clone())
+        }
+        _ => None,
+    };
+    let mut types: Vec<_> = args.chain(ret).collect();
+    for ty in types.iter_mut() {
+        Static.visit_type_mut(ty);
+    }
+    types
+}
+
+// the types a signature mentions that have a layout: arguments, return
+// types, and whatever they refer to or are generic over. async fns report
+// their future's output rather than the boxed future
+#[cfg(feature = "enabled")]
+fn reachable_types(sig: &Signature, new_sig: &Signature) -> Vec<syn::Type> {
+    use syn::visit_mut::VisitMut;
+
+    // only the impl's own lifetimes are in scope, and they don't change
+    // layouts anyway
+    struct Elide;
+
+    impl VisitMut for Elide {
+        fn visit_lifetime_mut(&mut self, lt: &mut syn::Lifetime) {
+            if lt.ident != "static" {
+                *lt = syn::Lifetime::new("'_", lt.span());
+            }
+        }
+    }
+
+    fn visit(ty: &syn::Type, out: &mut Vec<syn::Type>) {
+        match ty {
+            syn::Type::Path(p) => {
+                out.push(ty.clone());
+                if let Some(ref qself) = p.qself {
+                    visit(&qself.ty, out);
+                }
+                for segment in &p.path.segments {
+                    if let syn::PathArguments::AngleBracketed(ref args) = segment.arguments {
+                        for arg in &args.args {
+                            if let syn::GenericArgument::Type(ref t) = arg {
+                                visit(t, out);
+                            }
+                        }
+                    }
+                }
+            }
+            syn::Type::Tuple(t) => {
+                if !t.elems.is_empty() {
+                    out.push(ty.clone());
+                }
+                t.elems.iter().for_each(|t| visit(t, out));
+            }
+            syn::Type::Array(a) => {
+                out.push(ty.clone());
+                visit(&a.elem, out);
+            }
+            syn::Type::Reference(r) => visit(&r.elem, out),
+            syn::Type::Ptr(p) => visit(&p.elem, out),
+            syn::Type::Slice(s) => visit(&s.elem, out),
+            syn::Type::Paren(p) => visit(&p.elem, out),
+            syn::Type::Group(g) => visit(&g.elem, out),
+            _ => {}
+        }
+    }
+
+    let mut types = Vec::new();
+    for arg in &new_sig.inputs {
+        if let FnArg::Typed(typed) = arg {
+            visit(&typed.ty, &mut types);
+        }
+    }
+    if sig.receiver().is_some() {
+        types.push(syn::parse_quote! { Self });
+    }
+    let output = if sig.asyncness.is_some() {
+        &sig.output
+    } else {
+        &new_sig.output
     };
-    // println!("{}", ret);
-    ret
+    if let syn::ReturnType::Type(_, ref ty) = output {
+        visit(ty, &mut types);
+    }
+    let mut seen = std::collections::HashSet::new();
+    types.retain(|ty| seen.insert(quote!(#ty).to_string()));
+    for ty in types.iter_mut() {
+        Elide.visit_type_mut(ty);
+    }
+    types
+}
+
+// type and const params, in order
+#[cfg(feature = "enabled")]
+fn generic_args(generics: &syn::Generics) -> Vec<syn::Ident> {
+    generics
+        .params
+        .iter()
+        .filter_map(|p| match p {
+            syn::GenericParam::Type(t) => Some(t.ident.clone()),
+            syn::GenericParam::Const(c) => Some(c.ident.clone()),
+            syn::GenericParam::Lifetime(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(feature = "enabled")]
+fn impl_trait_arg(sig: &Signature) -> Option<&syn::Type> {
+    sig.inputs.iter().find_map(|arg| match arg {
+        FnArg::Typed(typed) => match *typed.ty {
+            syn::Type::ImplTrait(_) => Some(&*typed.ty),
+            _ => None,
+        },
+        FnArg::Receiver(_) => None,
+    })
 }
@@ -0,0 +1,41 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Overhead of calling through a `#[hot_reload]` wrapper, run with
+//! `cargo bench --features enabled,unstub`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+#[inline(never)]
+fn direct(x: u64) -> u64 {
+    x.wrapping_mul(31).rotate_left(7)
+}
+
+#[reloady::hot_reload]
+fn hot(x: u64) -> u64 {
+    x.wrapping_mul(31).rotate_left(7)
+}
+
+fn dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    group.bench_function("direct", |b| b.iter(|| direct(black_box(7))));
+    group.bench_function("hot_reload", |b| b.iter(|| hot(black_box(7))));
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
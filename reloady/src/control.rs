@@ -0,0 +1,116 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Control channel to `cargo hot-reload`.
+//!
+//! The CLI listens on the address in `RELOADY_CONTROL` and the app connects
+//! to it on init. Messages are single lines:
+//!
+//! - CLI -> app: `ready <version> <path>` once a build is complete and loadable
+//! - app -> CLI: `loaded <version>` or `failed <version> <reason>`
+//! - app -> CLI: `restart <reason>` right before exiting for a restart
+use std::{
+    io::{BufRead, BufReader, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+#[cfg(not(target_os = "windows"))]
+type Stream = std::os::unix::net::UnixStream;
+#[cfg(target_os = "windows")]
+type Stream = std::net::TcpStream;
+
+pub(crate) const CONTROL_ENV: &str = "RELOADY_CONTROL";
+
+pub(crate) struct Build {
+    pub version: usize,
+    pub path: String,
+}
+
+// load builds from the control thread as soon as they're announced
+static EAGER: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref __CONTROL: Mutex<Option<Stream>> = Mutex::new(None);
+    // newest build announced by the CLI and not loaded yet
+    static ref __PENDING_BUILD: Mutex<Option<Build>> = Mutex::new(None);
+}
+
+pub(crate) fn connect() {
+    let addr = match std::env::var(CONTROL_ENV) {
+        Ok(addr) => addr,
+        Err(_) => {
+            eprintln!("info: not started by cargo hot-reload, hot reloading is disabled");
+            return;
+        }
+    };
+    let stream = match Stream::connect(&addr) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("warning: couldn't connect to cargo hot-reload at {}: {}", addr, e);
+            return;
+        }
+    };
+    let reader = stream.try_clone().unwrap();
+    *__CONTROL.lock().unwrap() = Some(stream);
+    std::thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => break,
+            };
+            let mut parts = line.splitn(3, ' ');
+            match (
+                parts.next(),
+                parts.next().and_then(|v| v.parse().ok()),
+                parts.next(),
+            ) {
+                (Some("ready"), Some(version), Some(path)) => {
+                    let build = Build {
+                        version,
+                        path: path.to_string(),
+                    };
+                    crate::announce(build, EAGER.load(Ordering::SeqCst));
+                }
+                _ => eprintln!("warning: unknown control message {:?}", line),
+            }
+        }
+    });
+}
+
+pub(crate) fn set_eager(eager: bool) {
+    EAGER.store(eager, Ordering::SeqCst);
+    if eager {
+        crate::reload_pending();
+    }
+}
+
+pub(crate) fn set_pending_build(build: Build) {
+    *__PENDING_BUILD.lock().unwrap() = Some(build);
+}
+
+pub(crate) fn take_pending_build() -> Option<Build> {
+    __PENDING_BUILD.lock().unwrap().take()
+}
+
+pub(crate) fn report(msg: &str) {
+    if let Some(ref mut stream) = *__CONTROL.lock().unwrap() {
+        let _ = writeln!(stream, "{}", msg);
+    }
+}
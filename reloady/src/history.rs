@@ -0,0 +1,141 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Earlier versions of hot functions, for rolling back a bad edit without
+//! recompiling.
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// versions of each fn kept besides the original one
+static HISTORY_LEN: AtomicUsize = AtomicUsize::new(8);
+
+/// Why [`rollback`] or [`rollback_all`] couldn't roll back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RollbackError {
+    /// No hot function has this name.
+    UnknownFunction(String),
+    /// No build with this version has been loaded.
+    UnknownBuild(usize),
+    /// The build is too old to still be in the function's history.
+    Unavailable { name: String, version: usize },
+}
+
+impl std::fmt::Display for RollbackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RollbackError::UnknownFunction(name) => write!(f, "no hot function named {}", name),
+            RollbackError::UnknownBuild(version) => write!(f, "build {} was never loaded", version),
+            RollbackError::Unavailable { name, version } => {
+                write!(
+                    f,
+                    "no version of {} from build {} to roll back to",
+                    name, version
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RollbackError {}
+
+/// Sets how many earlier versions of each hot function are kept for
+/// [`rollback`], besides the original one. Defaults to 8.
+pub fn set_history_len(len: usize) {
+    HISTORY_LEN.store(len, Ordering::Relaxed);
+}
+
+#[cfg(feature = "unstub")]
+pub(crate) fn history_len() -> usize {
+    HISTORY_LEN.load(Ordering::Relaxed)
+}
+
+/// Makes a hot function run the version it ran in build `version` again, `0`
+/// being the original build.
+///
+/// `name` is the function's [`FunctionInfo::name`](crate::FunctionInfo),
+/// optionally with its module path in front. The next build swaps it like
+/// any other function.
+#[cfg(feature = "unstub")]
+pub fn rollback(name: &str, version: usize) -> Result<(), RollbackError> {
+    // otherwise the next call would load it over the rollback
+    crate::reload_pending();
+    if version > crate::most_recent_version() {
+        return Err(RollbackError::UnknownBuild(version));
+    }
+    let mut found = false;
+    for f in crate::registry::__HOT_FNS.lock().unwrap().values_mut() {
+        let full_name = format!("{}::{}", f.hot_fn.module_path, f.hot_fn.name);
+        if f.hot_fn.name != name && full_name != name {
+            continue;
+        }
+        found = true;
+        if !f.rollback(version) {
+            return Err(RollbackError::Unavailable {
+                name: name.to_string(),
+                version,
+            });
+        }
+    }
+    // the version it ran before may not be needed anymore
+    crate::unload::collect();
+    match found {
+        true => Ok(()),
+        false => Err(RollbackError::UnknownFunction(name.to_string())),
+    }
+}
+/// Makes a hot function run the version it ran in build `version` again, `0`
+/// being the original build.
+#[cfg(not(feature = "unstub"))]
+pub fn rollback(name: &str, _: usize) -> Result<(), RollbackError> {
+    Err(RollbackError::UnknownFunction(name.to_string()))
+}
+
+/// Makes every hot function run the version it ran in build `version` again.
+///
+/// Functions that were only registered after that build, or whose history
+/// doesn't go back that far, keep their current version and are listed in a
+/// warning.
+#[cfg(feature = "unstub")]
+pub fn rollback_all(version: usize) -> Result<(), RollbackError> {
+    crate::reload_pending();
+    if version > crate::most_recent_version() {
+        return Err(RollbackError::UnknownBuild(version));
+    }
+    let mut skipped = Vec::new();
+    for f in crate::registry::__HOT_FNS.lock().unwrap().values_mut() {
+        if !f.rollback(version) {
+            skipped.push(f.hot_fn.name);
+        }
+    }
+    crate::unload::collect();
+    if !skipped.is_empty() {
+        skipped.sort_unstable();
+        eprintln!(
+            "warning: no version from build {} of {}, keeping the current one",
+            version,
+            skipped.join(", ")
+        );
+    }
+    Ok(())
+}
+/// Makes every hot function run the version it ran in build `version` again.
+#[cfg(not(feature = "unstub"))]
+pub fn rollback_all(version: usize) -> Result<(), RollbackError> {
+    match version {
+        0 => Ok(()),
+        _ => Err(RollbackError::UnknownBuild(version)),
+    }
+}
@@ -0,0 +1,248 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Layouts of the types hot functions take and return, so a function isn't
+//! swapped to a build where they changed.
+use std::{
+    marker::PhantomData,
+    mem::{align_of, size_of},
+};
+
+/// Fingerprints a type's fields, so that hot functions taking or returning it
+/// aren't swapped when its fields change, even if its size stays the same.
+///
+/// Implement it with `#[derive(reloady::Layout)]`. Types that don't are only
+/// compared by size and alignment.
+pub trait Layout {
+    #[doc(hidden)]
+    const KIND: &'static str;
+    #[doc(hidden)]
+    fn fields() -> u64;
+}
+
+// reported by each hot fn's `__reloady_layouts`, for every type in its
+// signature
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TypeLayout {
+    pub kind: &'static str,
+    pub name: &'static str,
+    pub size: usize,
+    pub align: usize,
+    // 0 for types that don't implement Layout
+    pub fields: u64,
+}
+
+// reports the layouts of the types in a hot fn's signature
+#[doc(hidden)]
+pub type LayoutsFn = fn(&mut dyn FnMut(TypeLayout));
+
+// picks the most precise layout a type has, see __layout_of
+#[doc(hidden)]
+pub struct __Probe<T: ?Sized>(PhantomData<T>);
+
+#[allow(clippy::new_without_default)]
+impl<T: ?Sized> __Probe<T> {
+    pub const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+#[doc(hidden)]
+pub trait __ViaLayout {
+    fn layout(&self) -> TypeLayout;
+}
+
+impl<T: Layout> __ViaLayout for &&__Probe<T> {
+    fn layout(&self) -> TypeLayout {
+        TypeLayout {
+            kind: T::KIND,
+            fields: T::fields(),
+            ..sized_layout::<T>()
+        }
+    }
+}
+
+#[doc(hidden)]
+pub trait __ViaSized {
+    fn layout(&self) -> TypeLayout;
+}
+
+impl<T> __ViaSized for &__Probe<T> {
+    fn layout(&self) -> TypeLayout {
+        sized_layout::<T>()
+    }
+}
+
+fn sized_layout<T>() -> TypeLayout {
+    TypeLayout {
+        kind: "type",
+        name: std::any::type_name::<T>(),
+        size: size_of::<T>(),
+        align: align_of::<T>(),
+        fields: 0,
+    }
+}
+
+#[doc(hidden)]
+pub trait __ViaAny {
+    fn layout(&self) -> TypeLayout;
+}
+
+// unsized types are only ever behind pointers, which have their own layouts
+impl<T: ?Sized> __ViaAny for __Probe<T> {
+    fn layout(&self) -> TypeLayout {
+        TypeLayout {
+            kind: "type",
+            name: std::any::type_name::<T>(),
+            size: 0,
+            align: 0,
+            fields: 0,
+        }
+    }
+}
+
+/// The layout of a type, using its `Layout` impl if it has one. Method
+/// resolution tries `&&&__Probe<T>` first, so this only works where `T` is
+/// known.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __layout_of {
+    ($ty:ty) => {{
+        #[allow(unused_imports)]
+        use $crate::{__ViaAny, __ViaLayout, __ViaSized};
+        (&&&$crate::__Probe::<$ty>::new()).layout()
+    }};
+}
+
+// hashes the fields of a type deriving Layout
+#[doc(hidden)]
+pub struct __FieldHasher(u64);
+
+#[allow(clippy::new_without_default)]
+impl __FieldHasher {
+    pub fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    pub fn variant(&mut self, name: &str) {
+        self.bytes(name.as_bytes());
+    }
+
+    pub fn field(&mut self, name: &str, offset: usize, layout: TypeLayout) {
+        self.bytes(name.as_bytes());
+        self.bytes(layout.name.as_bytes());
+        for n in &[offset, layout.size, layout.align] {
+            self.0 = fnv1a(self.0, *n as u64);
+        }
+        self.0 = fnv1a(self.0, layout.fields);
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+        // separate names from what follows
+        self.0 = fnv1a(self.0, bytes.len() as u64);
+    }
+}
+
+/// A type whose layout is different in a new build than in the running app.
+#[derive(Debug, Clone)]
+pub struct LayoutChange {
+    /// `struct`, `enum` or `union`, or `type` if it doesn't derive
+    /// [`Layout`].
+    pub kind: String,
+    /// Full name of the type.
+    pub name: String,
+    /// Size and alignment in the running app.
+    pub expected: (usize, usize),
+    /// Size and alignment in the new build.
+    pub found: (usize, usize),
+}
+
+impl std::fmt::Display for LayoutChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} `{}` changed layout", self.kind, self.name)?;
+        if self.expected != self.found {
+            write!(
+                f,
+                " (size {} -> {}, align {} -> {})",
+                self.expected.0, self.found.0, self.expected.1, self.found.1
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// types whose layout differs between the running app's and a new build's
+// layouts fn
+#[cfg(feature = "unstub")]
+pub(crate) fn layout_changes(current: LayoutsFn, new: LayoutsFn) -> Vec<LayoutChange> {
+    let mut expected = Vec::new();
+    current(&mut |layout| expected.push(layout));
+    let mut changes = Vec::new();
+    new(&mut |found| {
+        let changed = expected
+            .iter()
+            .find(|l| l.name == found.name)
+            .filter(|l| (l.size, l.align, l.fields) != (found.size, found.align, found.fields));
+        if let Some(l) = changed {
+            changes.push(LayoutChange {
+                kind: found.kind.to_string(),
+                name: found.name.to_string(),
+                expected: (l.size, l.align),
+                found: (found.size, found.align),
+            });
+        }
+    });
+    changes
+}
+
+#[doc(hidden)]
+pub const fn __type_layout<T>() -> (usize, usize) {
+    (size_of::<T>(), align_of::<T>())
+}
+
+// FNV-1a, like the macro's signature hash
+#[doc(hidden)]
+pub const fn __deep_sighash(sighash: u64, layouts: &[(usize, usize)]) -> u64 {
+    let mut hash = sighash;
+    let mut i = 0;
+    while i < layouts.len() {
+        hash = fnv1a(hash, layouts[i].0 as u64);
+        hash = fnv1a(hash, layouts[i].1 as u64);
+        i += 1;
+    }
+    hash
+}
+
+const fn fnv1a(mut hash: u64, value: u64) -> u64 {
+    let bytes = value.to_le_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
@@ -15,101 +15,200 @@
  * You should have received a copy of the GNU Affero General Public License
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
-#![feature(label_break_value)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "unstub")]
+use std::sync::Mutex;
 
-use std::{collections::HashMap, fs::File, io::Read, sync::Mutex, time::SystemTime};
+#[cfg(feature = "unstub")]
+use lazy_static::lazy_static;
+#[cfg(feature = "unstub")]
+use libloading::Library;
 
 #[cfg(feature = "unstub")]
-pub use lazy_static::lazy_static;
+use layout::LayoutsFn;
 #[cfg(feature = "unstub")]
-use libloading::{Library, Symbol};
+use mismatch::handle_mismatch;
 #[cfg(feature = "unstub")]
-use symbolic::debuginfo::Object;
+use registry::__HOT_FNS;
 
-pub use reloady_impl::{hot_reload, init};
+pub use reloady_impl::{hot_reload, hot_state, init, migrate, on_reload, Layout};
 
 #[cfg(feature = "unstub")]
-lazy_static! {
-    static ref __CRATE_NAME: Mutex<Option<&'static str>> = Mutex::new(None);
-    static ref __MANIFEST_DIR: Mutex<Option<&'static str>> = Mutex::new(None);
-    static ref __MOST_RECENT_VERSION: Mutex<usize> = Mutex::new(0);
-    static ref __LIB_LAST_MODIFY_TIME: Mutex<SystemTime> = Mutex::new(SystemTime::UNIX_EPOCH);
-    static ref __CURRENT_LIB_REF: Mutex<Option<Library>> = Mutex::new(None);
-    static ref __CURRENT_DEBUGINFO: Mutex<Option<Debuginfo>> = Mutex::new(None);
-    static ref __LIB_VERSIONS: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
-}
-
-struct Debuginfo {
-    symbols: Vec<DemangledSymbol>,
+mod control;
+mod history;
+mod layout;
+mod lifecycle;
+#[cfg(all(feature = "unstub", feature = "nightly"))]
+mod mangled;
+mod mismatch;
+mod registry;
+mod state;
+#[cfg(feature = "unstub")]
+mod unload;
+
+pub use history::{rollback, rollback_all, set_history_len, RollbackError};
+pub use lifecycle::{on_after_reload, on_before_reload, Reload};
+pub use mismatch::{set_mismatch_policy, MismatchPolicy, SignatureMismatch, RESTART_EXIT_CODE};
+pub use registry::{functions, FunctionInfo};
+#[doc(hidden)]
+pub use registry::{HotFn, HotGenericFn};
+pub use state::{HotState, Migration};
+#[doc(hidden)]
+#[cfg(feature = "unstub")]
+pub use registry::__register_fn;
+pub use layout::{Layout, LayoutChange};
+#[doc(hidden)]
+pub use layout::{
+    TypeLayout, __FieldHasher, __Probe, __ViaAny, __ViaLayout, __ViaSized, __deep_sighash,
+    __type_layout,
+};
+#[doc(hidden)]
+pub use state::{__layout_hash, __take_state};
+#[doc(hidden)]
+#[cfg(feature = "unstub")]
+pub use unload::{__enter_call, __CallGuard};
+
+// set by the original process right after loading an image, so code in the
+// image can find the original process's symbols (e.g. hot state)
+#[doc(hidden)]
+#[no_mangle]
+pub static __RELOADY_HOST_RESOLVER: AtomicUsize = AtomicUsize::new(0);
+
+// set along with __RELOADY_HOST_RESOLVER, so hot fns in the image call each
+// other through the original process's hot fns instead of the image's own
+// wrappers, which never return
+#[doc(hidden)]
+#[no_mangle]
+pub static __RELOADY_HOST_DISPATCH: AtomicUsize = AtomicUsize::new(0);
+
+// windows executables only export the symbols the linker is told to, which
+// each object can do from its .drectve section
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __export {
+    ($name:expr) => {
+        #[cfg(target_os = "windows")]
+        const _: () = {
+            #[used]
+            #[link_section = ".drectve"]
+            static EXPORT: [u8; $name.len() + 11] = $crate::__export_directive($name);
+        };
+    };
 }
 
-struct DemangledSymbol {
-    mangled: String,
-    demangled: String,
+// ` /EXPORT:"name"`, `N` being the name's length plus 11
+#[doc(hidden)]
+pub const fn __export_directive<const N: usize>(name: &str) -> [u8; N] {
+    let prefix = b" /EXPORT:\"";
+    let name = name.as_bytes();
+    let mut directive = [b'"'; N];
+    let mut i = 0;
+    while i < prefix.len() + name.len() {
+        directive[i] = match i < prefix.len() {
+            true => prefix[i],
+            false => name[i - prefix.len()],
+        };
+        i += 1;
+    }
+    directive
 }
 
-impl Debuginfo {
-    #[cfg(feature = "unstub")]
-    pub fn new(obj: &Object) -> Self {
-        Self {
-            symbols: obj
-                .symbols()
-                .filter_map(|sym| sym.name().map(|x| x.to_string()))
-                // .inspect(|s| println!("Enumerating symbol {}", s))
-                .map(|n| DemangledSymbol {
-                    demangled: rustc_demangle::demangle(&n).to_string(),
-                    mangled: n,
-                })
-                .collect(),
-        }
-    }
+#[cfg(feature = "unstub")]
+lazy_static! {
+    static ref __MOST_RECENT_VERSION: Mutex<usize> = Mutex::new(0);
+    static ref __CURRENT_LIB_REF: Mutex<Option<Library>> = Mutex::new(None);
 }
 
-// set crate name for use later
+// connect to cargo hot-reload, which announces new builds
 #[cfg(feature = "unstub")]
-pub fn init2(crate_name: &'static str, manifest_dir: &'static str) {
-    let mut crate_name_guard = __CRATE_NAME.lock().unwrap();
-    *crate_name_guard = Some(crate_name);
-    let mut manifest_dir_guard = __MANIFEST_DIR.lock().unwrap();
-    *manifest_dir_guard = Some(manifest_dir);
+pub fn init2(_crate_name: &'static str, _manifest_dir: &'static str) {
+    control::connect();
 }
 #[cfg(not(feature = "unstub"))]
 pub fn init2(_: &'static str, _: &'static str) {}
 
-#[cfg(feature = "unstub")]
-fn crate_name() -> &'static str {
-    let crate_name = __CRATE_NAME.lock().unwrap();
-    crate_name.unwrap()
+// look up a symbol exported by the original process
+#[cfg(not(feature = "unstub"))]
+fn resolve_host_symbol(name: &str) -> *const () {
+    let resolver = __RELOADY_HOST_RESOLVER.load(Ordering::Acquire);
+    if resolver == 0 {
+        return std::ptr::null();
+    }
+    // SAFETY: only ever set to host_symbol by the original process
+    let resolver: fn(&str) -> *const () = unsafe { std::mem::transmute(resolver) };
+    resolver(name)
 }
 
-#[cfg(feature = "unstub")]
-fn manifest_dir() -> &'static str {
-    let manifest_dir = __MANIFEST_DIR.lock().unwrap();
-    manifest_dir.unwrap()
+// what a hot fn in this image calls for the hot fn whose impl in the image is
+// `impl_fn`: whatever the original process currently runs for it, or the
+// impl itself if the original process doesn't know the fn (e.g. a generic
+// instantiation only this build uses)
+#[doc(hidden)]
+#[cfg(not(feature = "unstub"))]
+pub fn __host_dispatch(impl_fn: *const ()) -> *const () {
+    let dispatch = __RELOADY_HOST_DISPATCH.load(Ordering::Acquire);
+    if dispatch == 0 {
+        return impl_fn;
+    }
+    // SAFETY: only ever set to registry::image_dispatch by the original process
+    let dispatch: fn(*const ()) -> *const () = unsafe { std::mem::transmute(dispatch) };
+    match dispatch(impl_fn) {
+        f if f.is_null() => impl_fn,
+        f => f,
+    }
 }
 
-#[cfg(all(feature = "unstub", not(target_os = "windows")))]
-fn get_app_path() -> String {
-    format!("{}/target/debug/{}", manifest_dir(), crate_name())
+// the original process only ever resolves to its own statics
+#[cfg(feature = "unstub")]
+fn resolve_host_symbol(_: &str) -> *const () {
+    std::ptr::null()
 }
 
-#[cfg(all(feature = "unstub", target_os = "windows"))]
-fn get_app_path() -> String {
-    format!("{}\\target\\debug\\{}.exe", manifest_dir(), crate_name())
+#[cfg(feature = "unstub")]
+fn host_symbol(name: &str) -> *const () {
+    #[cfg(not(target_os = "windows"))]
+    let this = libloading::os::unix::Library::this();
+    #[cfg(target_os = "windows")]
+    let this = match libloading::os::windows::Library::this() {
+        Ok(l) => l,
+        Err(_) => return std::ptr::null(),
+    };
+    // SAFETY: only the address is used, callers know the symbol's type
+    match unsafe { this.get::<*const ()>(name.as_bytes()) } {
+        Ok(sym) => {
+            // only hot states are looked up
+            state::register_state(name, *sym);
+            *sym
+        }
+        Err(_) => std::ptr::null(),
+    }
 }
 
-#[cfg(all(feature = "unstub", not(target_os = "windows")))]
-fn get_loadable_app_path() -> String {
-    get_app_path()
+// point a freshly loaded image back at this process
+#[cfg(feature = "unstub")]
+fn install_host_resolver(lib: &Library) {
+    // SAFETY: __RELOADY_HOST_RESOLVER is always an AtomicUsize
+    if let Ok(slot) = unsafe { lib.get::<*const AtomicUsize>(b"__RELOADY_HOST_RESOLVER") } {
+        let resolver: fn(&str) -> *const () = host_symbol;
+        unsafe { (**slot).store(resolver as usize, Ordering::Release) };
+    }
+    // SAFETY: as above
+    if let Ok(slot) = unsafe { lib.get::<*const AtomicUsize>(b"__RELOADY_HOST_DISPATCH") } {
+        let dispatch: fn(*const ()) -> *const () = registry::image_dispatch;
+        unsafe { (**slot).store(dispatch as usize, Ordering::Release) };
+    }
 }
 
-#[cfg(all(feature = "unstub", target_os = "windows"))]
-fn get_loadable_app_path() -> String {
+// copy the build, so the next build can overwrite it while it's loaded.
+// builds are always announced at the same path, which dlopen would only
+// load once
+#[cfg(feature = "unstub")]
+fn get_loadable_app_path(path: &str) -> String {
     use std::ffi::OsStr;
     use std::path::PathBuf;
-    // use std::time::SystemTime;
+    use std::time::SystemTime;
     // Copy
-    let path1 = PathBuf::from(get_app_path());
+    let path1 = PathBuf::from(path);
     let mut path2 = path1.file_name().unwrap().to_owned();
     let ts = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -121,205 +220,258 @@ fn get_loadable_app_path() -> String {
     path2.to_str().unwrap().to_string()
 }
 
-#[cfg(all(feature = "unstub", not(target_os = "windows")))]
-fn get_dbg_path() -> String {
-    format!("{}/target/debug/{}", manifest_dir(), crate_name())
+/// Load new builds as soon as `cargo hot-reload` announces them, instead of
+/// on the next call to a hot function.
+///
+/// Every hot function is swapped to the new build right away, so the next
+/// call doesn't pay for loading it.
+#[cfg(feature = "unstub")]
+pub fn set_eager_reload(eager: bool) {
+    control::set_eager(eager);
+}
+/// Load new builds as soon as `cargo hot-reload` announces them, instead of
+/// on the next call to a hot function.
+#[cfg(not(feature = "unstub"))]
+pub fn set_eager_reload(_: bool) {}
+
+/// Never unload the images of old builds.
+///
+/// By default an old build is unloaded once no hot function points into it
+/// and every call that started before that has returned. Code of the old
+/// build can still run after that if it's reachable some other way, e.g. a
+/// closure, trait object or `&'static str` it handed out, or a thread it
+/// spawned.
+#[cfg(feature = "unstub")]
+pub fn set_never_unload(never: bool) {
+    unload::set_never_unload(never);
+}
+/// Never unload the images of old builds.
+#[cfg(not(feature = "unstub"))]
+pub fn set_never_unload(_: bool) {}
+
+/// Check hot state for pointers into an old build before unloading it.
+///
+/// Values in hot state that point into a build's image, like a
+/// `&'static str`, a trait object's vtable or a function pointer, dangle
+/// once it's unloaded. With the check on, each hot state's value is scanned
+/// for such pointers before unloading a build (without following them), and
+/// the build is kept loaded with a warning naming the hot state instead. On
+/// platforms where reloady can't tell where a build is mapped (anything but
+/// Linux), old builds are always kept loaded with a warning.
+#[cfg(feature = "unstub")]
+pub fn set_check_dangling(check: bool) {
+    unload::set_check_dangling(check);
 }
+/// Check hot state for pointers into an old build before unloading it.
+#[cfg(not(feature = "unstub"))]
+pub fn set_check_dangling(_: bool) {}
 
-// possibly update the given fn ptr
+// a build was announced, load it now or on the next call to a hot fn
 #[cfg(feature = "unstub")]
-pub fn __update_fn<F: Copy>(
-    fn_name: &'static str,
-    _module_path: &'static str,
-    sighash: u64,
-    ptr: &Mutex<F>,
-) {
-    // get last updated time, and check
-    let app_path = get_app_path();
-    #[cfg(not(target_os = "windows"))]
-    let sym_name = format!("{}::{}", _module_path, fn_name);
-    #[cfg(target_os = "windows")]
-    let sym_name = fn_name.to_string();
-    // println!("App path = {} sym-name = {}", app_path, sym_name);
-    // if necessary, update this fn to latest version
-    {
-        let most_recent_version = {
-            let mrv = __MOST_RECENT_VERSION.lock().unwrap();
-            *mrv
-        };
-        let mut lib_versions = __LIB_VERSIONS.lock().unwrap();
-        //
-        if !lib_versions.contains_key(&sym_name) || lib_versions[&sym_name] < most_recent_version {
-            if let Some(ref current_lib) = *__CURRENT_LIB_REF.lock().unwrap() {
-                println!("input sighash = {}", sighash);
-                if !valid_symbol(current_lib, &sym_name, sighash) {
-                    panic!("ERR-PANIC: new lib's signature for {} does not match current signature, please restart!", sym_name);
-                } else {
-                    // update ptr
-                    *ptr.lock().unwrap() = *load_function::<F>(current_lib, &sym_name);
-                    // set version
-                    let old_version = lib_versions.insert(sym_name.clone(), most_recent_version);
-                    println!(
-                        "migrated fn {} from version {} -> {}",
-                        sym_name,
-                        old_version.unwrap_or(0),
-                        most_recent_version
-                    );
-                }
-            }
+pub(crate) fn announce(build: control::Build, eager: bool) {
+    let mut lib_ref = __CURRENT_LIB_REF.lock().unwrap();
+    if eager {
+        let loaded = load_build(&mut lib_ref, build);
+        drop(lib_ref);
+        finish_reload(loaded);
+    } else {
+        control::set_pending_build(build);
+        for f in __HOT_FNS.lock().unwrap().values() {
+            f.hot_fn.mark_pending();
         }
     }
-    // println!("Waiting for metadata");
-    let metadata = 'l: {
-        loop {
-            let res = std::fs::metadata(&app_path);
-            match res {
-                Ok(m) => break 'l m,
-                _ => continue,
-            }
-        }
-    };
-    {
-        let mut last_modify_time = __LIB_LAST_MODIFY_TIME.lock().unwrap();
-        let modified = metadata.modified().unwrap();
-        if modified > *last_modify_time {
-            // update
-            *last_modify_time = modified;
-        } else {
-            // println!("Fresh, nothing to do");
-            return;
-        }
-    }
-    // println!("Starting reload");
-
-    // 1. reload lib, while holding lib ref!!
-    {
-        // update version to next
-        let mut new_version = __MOST_RECENT_VERSION.lock().unwrap();
-        *new_version += 1;
-        println!("new version = {}", new_version);
-        let mut lib_ref = __CURRENT_LIB_REF.lock().unwrap();
-
-        if let Some(old_lib) = lib_ref.take() {
-            println!("dropped old lib");
-            old_lib.close().unwrap();
-        }
-
-        eprintln!("info: loading new lib for function {}", sym_name);
-
-        // SAFETY: app_path always resolves to a valid exe
-        let new_lib = 'l: {
-            loop {
-                let res = unsafe { Library::new(get_loadable_app_path()) };
-                match res {
-                    Ok(l) => break 'l l,
-                    Err(_) => continue,
-                }
-            }
-        };
-        *lib_ref = Some(new_lib);
-
-        // 0. update debuginfo
-        update_debuginfo();
-
-        let lr = lib_ref.as_ref().unwrap();
-
-        // 1.5. check symbol validity
-        if !valid_symbol(lr, &sym_name, sighash) {
-            panic!(
-                "ERR-PANIC: new lib's signature for {} does not match current signature, please restart!",
-                sym_name
-            );
-        }
-
-        // 2. load new symbol
-        let f = load_function::<F>(lr, &sym_name);
-
-        // 3. swap ptr
-        *ptr.lock().unwrap() = *f;
+}
 
-        // 4. update lib version
-        let mut lib_versions = __LIB_VERSIONS.lock().unwrap();
-        lib_versions.insert(sym_name, *new_version);
+// load the announced build, if another call didn't already
+#[cfg(feature = "unstub")]
+pub(crate) fn reload_pending() {
+    let mut lib_ref = __CURRENT_LIB_REF.lock().unwrap();
+    if let Some(build) = control::take_pending_build() {
+        let loaded = load_build(&mut lib_ref, build);
+        drop(lib_ref);
+        finish_reload(loaded);
     }
 }
 
-#[cfg(not(feature = "unstub"))]
-pub fn __update_fn<F: Copy>(_: &'static str, _: &'static str, _: u64, _: &Mutex<F>) {}
+#[cfg(feature = "unstub")]
+pub(crate) fn most_recent_version() -> usize {
+    *__MOST_RECENT_VERSION.lock().unwrap()
+}
 
+// generic instantiations are registered on their first call, possibly after
+// builds were loaded
 #[cfg(feature = "unstub")]
-fn update_debuginfo() {
-    // load debuginfo to find symbols
-    let debuginfo_bytes = {
-        #[cfg(not(target_os = "windows"))]
-        let mut f = File::open(get_dbg_path()).unwrap();
-        #[cfg(target_os = "windows")]
-        let mut f = File::open(get_app_path()).unwrap();
-        let mut buf = Vec::new();
-        f.read_to_end(&mut buf).unwrap();
-        buf
+pub(crate) fn register_instance(hot_fn: &'static registry::HotFn) {
+    let lib_ref = __CURRENT_LIB_REF.lock().unwrap();
+    registry::__register_fn(hot_fn);
+    let lib = match *lib_ref {
+        Some(ref lib) => lib,
+        None => return,
     };
-    let debuginfo = Object::parse(&debuginfo_bytes).unwrap();
-    let debuginfo = Debuginfo::new(&debuginfo);
-    {
-        let mut debuginfo_current = __CURRENT_DEBUGINFO.lock().unwrap();
-        drop(debuginfo_current.take());
-        *debuginfo_current = Some(debuginfo);
+    let sym_name = registry::sym_name(hot_fn);
+    match resolve(lib, hot_fn) {
+        Ok(addr) => {
+            let version = *__MOST_RECENT_VERSION.lock().unwrap();
+            let mut hot_fns = __HOT_FNS.lock().unwrap();
+            let f = hot_fns.get_mut(&sym_name).unwrap();
+            // SAFETY: the lib's symbol has the same signature hash
+            unsafe { f.swap(version, addr) };
+            f.forget_before(version);
+        }
+        // keep calling this build's version, like fns whose signature changed
+        Err(_) => eprintln!(
+            "warning: {} is not in the current lib, keeping the previous version",
+            sym_name
+        ),
     }
 }
 
-struct DebuginfoGuard<'a> {
-    guard: std::sync::MutexGuard<'a, Option<Debuginfo>>,
+// a build that was loaded, with the hot fns that can be swapped to it
+#[cfg(feature = "unstub")]
+struct Loaded {
+    reload: Reload,
+    // addresses in the new lib, by symbol name
+    swaps: Vec<(String, *const ())>,
+    mismatches: Vec<SignatureMismatch>,
 }
 
-impl<'a> std::ops::Deref for DebuginfoGuard<'a> {
-    type Target = Debuginfo;
-
-    fn deref(&self) -> &Self::Target {
-        self.guard.as_ref().unwrap()
+// callbacks may call hot fns or reload, so no locks may be held
+#[cfg(feature = "unstub")]
+fn finish_reload(loaded: Option<Loaded>) {
+    let loaded = match loaded {
+        Some(loaded) => loaded,
+        None => return,
+    };
+    let version = loaded.reload.new_version;
+    lifecycle::before_reload(&loaded.reload);
+    {
+        let _lib_ref = __CURRENT_LIB_REF.lock().unwrap();
+        // a newer build was loaded while the callbacks ran
+        if most_recent_version() != version {
+            return;
+        }
+        let mut hot_fns = __HOT_FNS.lock().unwrap();
+        for (sym_name, addr) in loaded.swaps {
+            let f = hot_fns.get_mut(&sym_name).unwrap();
+            // SAFETY: the new lib's symbol has the same signature hash
+            unsafe { f.swap(version, addr) };
+        }
+    }
+    lifecycle::after_reload(&loaded.reload);
+    unload::collect();
+    control::report(&format!("loaded {}", version));
+    for mismatch in loaded.mismatches {
+        handle_mismatch(mismatch);
     }
 }
 
+// load a build announced by the CLI and make it the current lib, hot fns
+// keep calling their current version until finish_reload swaps them
 #[cfg(feature = "unstub")]
-fn get_debuginfo<'a>() -> DebuginfoGuard<'a> {
-    let debuginfo_current = __CURRENT_DEBUGINFO.lock().unwrap();
-    DebuginfoGuard {
-        guard: debuginfo_current,
+fn load_build(lib_ref: &mut Option<Library>, build: control::Build) -> Option<Loaded> {
+    eprintln!("info: loading build {} from {}", build.version, build.path);
+
+    let path = get_loadable_app_path(&build.path);
+    // SAFETY: the CLI only announces complete, loadable builds
+    let new_lib = match unsafe { Library::new(&path) } {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("warning: couldn't load build {}: {}", build.version, e);
+            control::report(&format!("failed {} {}", build.version, e));
+            for f in __HOT_FNS.lock().unwrap().values() {
+                f.hot_fn.keep_current();
+            }
+            return None;
+        }
+    };
+    install_host_resolver(&new_lib);
+    unload::loaded(build.version, &path);
+    let old_version = most_recent_version();
+    if let Some(old_lib) = lib_ref.replace(new_lib) {
+        // functions that failed to migrate, or can be rolled back, may still
+        // point into it
+        unload::retire(old_version, old_lib);
+    }
+
+    // generic fns can only be found by their mangled names
+    #[cfg(feature = "nightly")]
+    mangled::update(&build.path);
+    *__MOST_RECENT_VERSION.lock().unwrap() = build.version;
+    println!("new version = {}", build.version);
+
+    let lib = lib_ref.as_ref().unwrap();
+    let mut functions = Vec::new();
+    let mut swaps = Vec::new();
+    let mut mismatches = Vec::new();
+    for (sym_name, f) in __HOT_FNS.lock().unwrap().iter() {
+        // calls made until the swap, e.g. by callbacks, run the current version
+        f.hot_fn.keep_current();
+        match resolve(lib, f.hot_fn) {
+            Ok(addr) => {
+                functions.push(f.hot_fn.name);
+                swaps.push((sym_name.clone(), addr));
+            }
+            Err((found, layout_changes)) => {
+                mismatches.push(SignatureMismatch {
+                    name: sym_name.clone(),
+                    version: build.version,
+                    expected: f.hot_fn.sighash,
+                    found,
+                    layout_changes,
+                });
+            }
+        }
     }
+    functions.sort_unstable();
+    Some(Loaded {
+        reload: Reload {
+            old_version,
+            new_version: build.version,
+            functions,
+        },
+        swaps,
+        mismatches,
+    })
 }
 
+// the lib's version of the fn, or the signature hash it has in the lib and
+// the types whose layout changed if it doesn't match
 #[cfg(feature = "unstub")]
-fn load_function<'a, F>(lib: &'a Library, name: &str) -> Symbol<'a, F> {
-    let debuginfo = get_debuginfo();
-    for sym in &debuginfo.symbols {
-        if sym.demangled.starts_with(name)
-            && sym
-                .demangled
-                .split("::")
-                .all(|x| !x.ends_with("__reloady_sighash"))
-        {
-            // SAFETY: validated the lib contains the given symbol
-            return unsafe { lib.get(sym.mangled.as_bytes()).unwrap() };
-        }
+fn resolve(
+    lib: &Library,
+    hot_fn: &registry::HotFn,
+) -> Result<*const (), (Option<u64>, Vec<LayoutChange>)> {
+    let found = symbol::<fn() -> u64>(lib, hot_fn, &registry::sighash_sym_name(hot_fn));
+    let found = found.map(|sighash| sighash());
+    if found != Some(hot_fn.sighash) {
+        return Err((found, Vec::new()));
     }
-    panic!("Couldn't find symbol");
+    let changes = match symbol::<LayoutsFn>(lib, hot_fn, &registry::layouts_sym_name(hot_fn)) {
+        Some(layouts) => layout::layout_changes(hot_fn.layouts, layouts),
+        None => return Err((None, Vec::new())),
+    };
+    if !changes.is_empty() {
+        return Err((found, changes));
+    }
+    // e.g. generic instantiations the new build doesn't use anymore
+    symbol(lib, hot_fn, &registry::sym_name(hot_fn)).ok_or((None, Vec::new()))
 }
 
+// one of the lib's symbols for a hot fn, `T` being its type
 #[cfg(feature = "unstub")]
-fn valid_symbol<'a>(lib: &'a Library, name: &str, sighash_val: u64) -> bool {
-    let debuginfo = get_debuginfo();
-    let check_name = format!("{}__reloady_sighash", name);
-    // println!("test name = {}", check_name);
-    for sym in &debuginfo.symbols {
-        // println!("Trying symbol {}", sym.mangled);
-        if sym.demangled.starts_with(&check_name) {
-            // SAFETY: validated the lib contains the given symbol
-            let sym_value: Symbol<fn() -> u64> =
-                unsafe { lib.get(sym.mangled.as_bytes()).unwrap() };
-            let sym_value = (*sym_value)();
-            println!("found sym value = {}, input = {}", sym_value, sighash_val);
-            return sym_value == sighash_val;
-        }
+fn symbol<T: Copy>(lib: &Library, hot_fn: &registry::HotFn, name: &str) -> Option<T> {
+    #[cfg(feature = "nightly")]
+    if !registry::exported_by_name(hot_fn) {
+        let mangled = mangled::find(name)?;
+        // SAFETY: the symbol was exported for this hot fn
+        return unsafe { lib.get::<T>(mangled.as_bytes()) }
+            .ok()
+            .map(|sym| *sym);
     }
-    false
+    #[cfg(not(feature = "nightly"))]
+    let _ = hot_fn;
+    // SAFETY: see above
+    unsafe { lib.get::<T>(name.as_bytes()) }
+        .ok()
+        .map(|sym| *sym)
 }
@@ -0,0 +1,83 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Callbacks around swapping hot functions to a new build.
+#[cfg(feature = "unstub")]
+use std::sync::Mutex;
+
+/// A reload, as passed to reload callbacks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reload {
+    /// Version of the build loaded before, `0` for the original build.
+    pub old_version: usize,
+    /// Version of the new build.
+    pub new_version: usize,
+    /// The hot functions swapped to the new build, as named by
+    /// [`FunctionInfo::name`](crate::FunctionInfo), sorted. Functions whose
+    /// signature changed keep their previous version and aren't listed.
+    pub functions: Vec<&'static str>,
+}
+
+#[cfg(feature = "unstub")]
+lazy_static::lazy_static! {
+    static ref __BEFORE: Mutex<Vec<fn(&Reload)>> = Mutex::new(Vec::new());
+    static ref __AFTER: Mutex<Vec<fn(&Reload)>> = Mutex::new(Vec::new());
+}
+
+/// Calls `callback` whenever a new build was loaded, right before hot
+/// functions are swapped to it, while every hot function still runs its
+/// previous version.
+///
+/// Callbacks run on the thread that loads the build, and may call hot
+/// functions.
+#[cfg(feature = "unstub")]
+pub fn on_before_reload(callback: fn(&Reload)) {
+    __BEFORE.lock().unwrap().push(callback);
+}
+/// Calls `callback` whenever a new build was loaded, right before hot
+/// functions are swapped to it.
+#[cfg(not(feature = "unstub"))]
+pub fn on_before_reload(_: fn(&Reload)) {}
+
+/// Calls `callback` right after hot functions were swapped to a new build.
+///
+/// Callbacks run on the thread that loads the build, and may call hot
+/// functions.
+#[cfg(feature = "unstub")]
+pub fn on_after_reload(callback: fn(&Reload)) {
+    __AFTER.lock().unwrap().push(callback);
+}
+/// Calls `callback` right after hot functions were swapped to a new build.
+#[cfg(not(feature = "unstub"))]
+pub fn on_after_reload(_: fn(&Reload)) {}
+
+// callbacks may register more callbacks, so the lock isn't held while they run
+#[cfg(feature = "unstub")]
+pub(crate) fn before_reload(reload: &Reload) {
+    let callbacks = __BEFORE.lock().unwrap().clone();
+    for callback in callbacks {
+        callback(reload);
+    }
+}
+
+#[cfg(feature = "unstub")]
+pub(crate) fn after_reload(reload: &Reload) {
+    let callbacks = __AFTER.lock().unwrap().clone();
+    for callback in callbacks {
+        callback(reload);
+    }
+}
@@ -0,0 +1,57 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Finding instantiations of generic hot functions, which can't be given an
+//! export name, by reading each build's symbol table.
+use std::{collections::HashMap, fs, sync::Mutex};
+
+use symbolic::debuginfo::Object;
+
+lazy_static::lazy_static! {
+    // mangled symbol names of the current build, keyed by their demangled
+    // name without hashes, so names match across builds
+    static ref __SYMBOLS: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+pub(crate) fn update(path: &str) {
+    let mut symbols = __SYMBOLS.lock().unwrap();
+    symbols.clear();
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("warning: couldn't read symbols of {}: {}", path, e);
+            return;
+        }
+    };
+    let object = match Object::parse(&bytes) {
+        Ok(object) => object,
+        Err(e) => {
+            eprintln!("warning: couldn't read symbols of {}: {}", path, e);
+            return;
+        }
+    };
+    for name in object
+        .symbols()
+        .filter_map(|sym| sym.name().map(|n| n.to_string()))
+    {
+        symbols.insert(format!("{:#}", rustc_demangle::demangle(&name)), name);
+    }
+}
+
+pub(crate) fn find(name: &str) -> Option<String> {
+    __SYMBOLS.lock().unwrap().get(name).cloned()
+}
@@ -0,0 +1,94 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+#[cfg(feature = "unstub")]
+use std::sync::Mutex;
+
+use crate::layout::LayoutChange;
+
+/// Exit code the app uses to ask `cargo hot-reload` for a full restart.
+pub const RESTART_EXIT_CODE: i32 = 75;
+
+/// A reloaded function whose signature no longer matches the running app.
+#[derive(Debug, Clone)]
+pub struct SignatureMismatch {
+    /// Symbol name of the function.
+    pub name: String,
+    /// Version of the image that failed to load the function.
+    pub version: usize,
+    /// Signature hash the running app was compiled with.
+    pub expected: u64,
+    /// Signature hash in the new image, or `None` if the function is gone.
+    pub found: Option<u64>,
+    /// Types in the signature whose layout changed, if the signatures match.
+    pub layout_changes: Vec<LayoutChange>,
+}
+
+/// What to do when a reloaded function's signature no longer matches.
+///
+/// Set with [`set_mismatch_policy`], defaults to [`MismatchPolicy::KeepOld`].
+#[derive(Clone, Copy)]
+pub enum MismatchPolicy {
+    /// Keep calling the previous version of the function and report the mismatch.
+    KeepOld,
+    /// Exit with [`RESTART_EXIT_CODE`] so `cargo hot-reload` restarts the app.
+    Restart,
+    /// Keep calling the previous version and hand the mismatch to a callback.
+    Callback(fn(&SignatureMismatch)),
+}
+
+#[cfg(feature = "unstub")]
+lazy_static::lazy_static! {
+    static ref __MISMATCH_POLICY: Mutex<MismatchPolicy> = Mutex::new(MismatchPolicy::KeepOld);
+}
+
+/// Sets what happens when a reloaded function's signature changed.
+#[cfg(feature = "unstub")]
+pub fn set_mismatch_policy(policy: MismatchPolicy) {
+    *__MISMATCH_POLICY.lock().unwrap() = policy;
+}
+/// Sets what happens when a reloaded function's signature changed.
+#[cfg(not(feature = "unstub"))]
+pub fn set_mismatch_policy(_: MismatchPolicy) {}
+
+// returns once the old version of the function should keep being used
+#[cfg(feature = "unstub")]
+pub(crate) fn handle_mismatch(mismatch: SignatureMismatch) {
+    let policy = *__MISMATCH_POLICY.lock().unwrap();
+    let reason = match mismatch.layout_changes.first() {
+        Some(change) => format!("{} in the new lib, which {} uses", change, mismatch.name),
+        None => format!(
+            "new lib's signature for {} does not match current signature",
+            mismatch.name
+        ),
+    };
+    match policy {
+        MismatchPolicy::KeepOld => {
+            eprintln!("warning: {}, keeping the previous version", reason)
+        }
+        MismatchPolicy::Restart => {
+            eprintln!("info: {}, restarting", reason);
+            let report = match mismatch.layout_changes.first() {
+                Some(change) => format!("restart {}", change),
+                None => format!("restart signature of {} changed", mismatch.name),
+            };
+            crate::control::report(&report);
+            std::process::exit(RESTART_EXIT_CODE);
+        }
+        MismatchPolicy::Callback(f) => f(&mismatch),
+    }
+}
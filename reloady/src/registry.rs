@@ -0,0 +1,430 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Every hot function in the running app.
+//!
+//! `#[hot_reload]` registers each free function from a constructor, so they
+//! are all listed before `main` runs. Methods and generic instantiations are
+//! registered when they're first called.
+use std::sync::{
+    atomic::{AtomicPtr, Ordering},
+    RwLock,
+};
+use std::time::SystemTime;
+#[cfg(feature = "unstub")]
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::layout::LayoutsFn;
+
+/// A hot-reloadable function, as returned by [`functions`].
+#[derive(Debug, Clone)]
+pub struct FunctionInfo {
+    /// Name of the function, `Type::method` for methods.
+    pub name: &'static str,
+    /// Module the function is defined in.
+    pub module_path: &'static str,
+    /// Signature hash the running app was compiled with.
+    pub sighash: u64,
+    /// Version of the build the function is running from, `0` for the
+    /// original build.
+    pub version: usize,
+    /// When the function was last swapped to a new build.
+    pub last_reload: Option<SystemTime>,
+    /// Builds the function can be rolled back to, oldest first.
+    pub versions: Vec<usize>,
+}
+
+// one per hot fn, calls load `dispatch` and jump to it
+#[doc(hidden)]
+pub struct HotFn {
+    pub name: &'static str,
+    pub impl_name: &'static str,
+    pub module_path: &'static str,
+    pub sighash: u64,
+    // type the fn is a method of, empty for free fns
+    pub self_ty: &'static str,
+    // generic args of the instantiation, empty if the fn isn't generic
+    pub args: &'static str,
+    // reports the layouts of the types in the signature
+    pub layouts: LayoutsFn,
+    // `current`, or `update` while a build is pending
+    dispatch: AtomicPtr<()>,
+    // newest compatible version of the fn
+    current: AtomicPtr<()>,
+    // has the fn's signature, loads the pending build and calls `current`
+    #[cfg_attr(not(feature = "unstub"), allow(dead_code))]
+    update: *const (),
+}
+
+// SAFETY: update is never written to
+unsafe impl Sync for HotFn {}
+
+impl HotFn {
+    pub const fn new(
+        name: &'static str,
+        impl_name: &'static str,
+        module_path: &'static str,
+        sighash: u64,
+        layouts: LayoutsFn,
+        original: *const (),
+        update: *const (),
+    ) -> Self {
+        Self {
+            name,
+            impl_name,
+            module_path,
+            sighash,
+            self_ty: "",
+            args: "",
+            layouts,
+            dispatch: AtomicPtr::new(original as *mut ()),
+            current: AtomicPtr::new(original as *mut ()),
+            update,
+        }
+    }
+
+    #[inline(always)]
+    pub fn get(&self) -> *const () {
+        self.dispatch.load(Ordering::Acquire)
+    }
+
+    pub fn current(&self) -> *const () {
+        self.current.load(Ordering::Acquire)
+    }
+
+    // called by `update`
+    #[cfg(feature = "unstub")]
+    pub fn reload_pending(&self) {
+        crate::reload_pending();
+    }
+    #[cfg(not(feature = "unstub"))]
+    pub fn reload_pending(&self) {}
+
+    // SAFETY: addr must be a function with this fn's signature
+    #[cfg(feature = "unstub")]
+    pub(crate) unsafe fn set(&self, addr: *const ()) {
+        self.current.store(addr as *mut (), Ordering::Release);
+        self.dispatch.store(addr as *mut (), Ordering::Release);
+    }
+
+    // route the next call through `update`
+    #[cfg(feature = "unstub")]
+    pub(crate) fn mark_pending(&self) {
+        self.dispatch
+            .store(self.update as *mut (), Ordering::Release);
+    }
+
+    // keep calling the current version
+    #[cfg(feature = "unstub")]
+    pub(crate) fn keep_current(&self) {
+        self.dispatch
+            .store(self.current() as *mut (), Ordering::Release);
+    }
+}
+
+// one per generic hot fn or hot method, with a HotFn for every instantiation
+// that has been called
+#[doc(hidden)]
+pub struct HotGenericFn {
+    pub name: &'static str,
+    pub impl_name: &'static str,
+    pub module_path: &'static str,
+    // keyed by a type name that's unique to the instantiation
+    instances: RwLock<Vec<(&'static str, &'static HotFn)>>,
+}
+
+impl HotGenericFn {
+    pub const fn new(
+        name: &'static str,
+        impl_name: &'static str,
+        module_path: &'static str,
+    ) -> Self {
+        Self {
+            name,
+            impl_name,
+            module_path,
+            instances: RwLock::new(Vec::new()),
+        }
+    }
+
+    // `self_ty` and `args` are the instantiation's `Self` and generic args,
+    // as they appear in its demangled symbol name. signature hashes can
+    // depend on them, so every instantiation has its own
+    #[allow(clippy::too_many_arguments)]
+    pub fn instance<A: FnOnce() -> Vec<String>>(
+        &'static self,
+        key: &'static str,
+        self_ty: &'static str,
+        args: A,
+        sighash: u64,
+        layouts: LayoutsFn,
+        original: *const (),
+        update: *const (),
+    ) -> &'static HotFn {
+        if let Some(hot_fn) = find_instance(&self.instances.read().unwrap(), key) {
+            return hot_fn;
+        }
+        let mut instances = self.instances.write().unwrap();
+        if let Some(hot_fn) = find_instance(&instances, key) {
+            return hot_fn;
+        }
+        let args = leak(args().join(", "));
+        // `crate::module::Foo<T>` is just `Foo<T>` next to the module path
+        let short_ty = self_ty
+            .strip_prefix(self.module_path)
+            .and_then(|ty| ty.strip_prefix("::"))
+            .unwrap_or(self_ty);
+        let name = match (self_ty, self.name.rsplit_once("::")) {
+            ("", _) => self.name.to_string(),
+            // trait methods are named `Trait::method`
+            (_, Some((trait_name, method))) => {
+                format!("<{} as {}>::{}", short_ty, trait_name, method)
+            }
+            (_, None) => format!("{}::{}", short_ty, self.name),
+        };
+        let name = match args {
+            "" => name,
+            _ => format!("{}::<{}>", name, args),
+        };
+        let hot_fn = Box::leak(Box::new(HotFn {
+            self_ty,
+            args,
+            ..HotFn::new(
+                leak(name),
+                self.impl_name,
+                self.module_path,
+                sighash,
+                layouts,
+                original,
+                update,
+            )
+        }));
+        #[cfg(feature = "unstub")]
+        crate::register_instance(hot_fn);
+        instances.push((key, hot_fn));
+        hot_fn
+    }
+}
+
+fn find_instance(
+    instances: &[(&'static str, &'static HotFn)],
+    key: &str,
+) -> Option<&'static HotFn> {
+    instances
+        .iter()
+        .find(|(k, _)| std::ptr::eq(*k, key) || *k == key)
+        .map(|&(_, hot_fn)| hot_fn)
+}
+
+fn leak(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
+#[cfg(feature = "unstub")]
+pub(crate) struct Registered {
+    pub hot_fn: &'static HotFn,
+    // build the fn's pointer comes from
+    pub version: usize,
+    pub last_reload: Option<SystemTime>,
+    // the builds the fn changed in and its address in each, the original
+    // build first
+    history: Vec<(usize, usize)>,
+    // oldest build the history knows what the fn ran in, besides the
+    // original one
+    oldest: usize,
+}
+
+#[cfg(feature = "unstub")]
+impl Registered {
+    fn new(hot_fn: &'static HotFn) -> Self {
+        Self {
+            hot_fn,
+            version: 0,
+            last_reload: None,
+            history: vec![(0, hot_fn.current() as usize)],
+            oldest: 0,
+        }
+    }
+
+    // SAFETY: addr must be a function with this fn's signature
+    pub unsafe fn swap(&mut self, version: usize, addr: *const ()) {
+        self.hot_fn.set(addr);
+        __IMAGE_FNS
+            .lock()
+            .unwrap()
+            .insert(addr as usize, (version, self.hot_fn));
+        self.version = version;
+        self.last_reload = Some(SystemTime::now());
+        self.history.push((version, addr as usize));
+        // the original build is never unloaded, so it's always kept
+        if self.history.len() > crate::history::history_len().max(1) + 1 {
+            self.history.remove(1);
+            self.oldest = self.history.get(1).map_or(version, |&(v, _)| v);
+        }
+    }
+
+    // builds before this one may have had other versions of the fn
+    pub fn forget_before(&mut self, version: usize) {
+        self.oldest = version;
+    }
+
+    // go back to the version the fn ran in build `version`, returns whether
+    // the history goes back that far
+    pub fn rollback(&mut self, version: usize) -> bool {
+        if version != 0 && version < self.oldest {
+            return false;
+        }
+        let &(from, addr) = match self.history.iter().rev().find(|&&(v, _)| v <= version) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        // SAFETY: every address in the history passed the signature check
+        unsafe { self.hot_fn.set(addr as *const ()) };
+        self.version = from;
+        self.last_reload = Some(SystemTime::now());
+        true
+    }
+
+    // whether the fn runs or can be rolled back to build `version`
+    pub fn uses(&self, version: usize) -> bool {
+        self.version == version || self.history.iter().any(|&(v, _)| v == version)
+    }
+
+    fn versions(&self) -> Vec<usize> {
+        self.history.iter().map(|&(v, _)| v).collect()
+    }
+}
+
+#[cfg(feature = "unstub")]
+lazy_static::lazy_static! {
+    // keyed by symbol name
+    pub(crate) static ref __HOT_FNS: Mutex<HashMap<String, Registered>> = Mutex::new(HashMap::new());
+    // keyed by the address of the fn's impl in a loaded build, with the
+    // build's version
+    static ref __IMAGE_FNS: Mutex<HashMap<usize, (usize, &'static HotFn)>> = Mutex::new(HashMap::new());
+}
+
+// where a call from a loaded build to the hot fn whose impl is at `addr` in
+// that build should go, null if the fn was never swapped to that build
+#[cfg(feature = "unstub")]
+pub(crate) fn image_dispatch(addr: *const ()) -> *const () {
+    match __IMAGE_FNS.lock().unwrap().get(&(addr as usize)) {
+        Some((_, hot_fn)) => hot_fn.get(),
+        None => std::ptr::null(),
+    }
+}
+
+// build `version` was unloaded, another build may be mapped at its addresses
+#[cfg(feature = "unstub")]
+pub(crate) fn forget_image(version: usize) {
+    __IMAGE_FNS
+        .lock()
+        .unwrap()
+        .retain(|_, &mut (v, _)| v != version);
+}
+
+// the name images export a hot fn's impl under, without generic args
+#[cfg(feature = "unstub")]
+pub(crate) fn base_sym_name(hot_fn: &HotFn) -> String {
+    match hot_fn.self_ty {
+        // methods of generic impls are named after the actual `Self`
+        self_ty if !self_ty.is_empty() && !hot_fn.impl_name.contains("::") => {
+            format!("<{}>::{}", self_ty, hot_fn.impl_name)
+        }
+        _ => hot_fn.impl_name.to_string(),
+    }
+}
+
+// whether the image exports the fn under `sym_name`, otherwise it's only
+// found by its mangled name
+#[cfg(all(feature = "unstub", feature = "nightly"))]
+pub(crate) fn exported_by_name(hot_fn: &HotFn) -> bool {
+    hot_fn.args.is_empty() && (hot_fn.self_ty.is_empty() || hot_fn.impl_name.contains("::"))
+}
+
+#[cfg(feature = "unstub")]
+pub(crate) fn sym_name(hot_fn: &HotFn) -> String {
+    with_args(base_sym_name(hot_fn), hot_fn)
+}
+
+// the fn returning the signature hash of a hot fn's impl
+#[cfg(feature = "unstub")]
+pub(crate) fn sighash_sym_name(hot_fn: &HotFn) -> String {
+    with_args(
+        format!("{}__reloady_sighash", base_sym_name(hot_fn)),
+        hot_fn,
+    )
+}
+
+// the fn reporting the layouts of the types in a hot fn's signature
+#[cfg(feature = "unstub")]
+pub(crate) fn layouts_sym_name(hot_fn: &HotFn) -> String {
+    with_args(
+        format!("{}__reloady_layouts", base_sym_name(hot_fn)),
+        hot_fn,
+    )
+}
+
+#[cfg(feature = "unstub")]
+fn with_args(name: String, hot_fn: &HotFn) -> String {
+    match hot_fn.args {
+        "" => name,
+        args => format!("{}::<{}>", name, args),
+    }
+}
+
+// called from each hot fn's constructor
+#[doc(hidden)]
+#[cfg(feature = "unstub")]
+pub fn __register_fn(hot_fn: &'static HotFn) {
+    __HOT_FNS
+        .lock()
+        .unwrap()
+        .entry(sym_name(hot_fn))
+        .or_insert_with(|| Registered::new(hot_fn));
+}
+
+/// Lists every hot-reloadable function in the app, sorted by module and name.
+///
+/// Generic functions are listed once for every instantiation that has been
+/// called, and methods once they have been called.
+#[cfg(feature = "unstub")]
+pub fn functions() -> Vec<FunctionInfo> {
+    let mut fns: Vec<_> = __HOT_FNS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|f| FunctionInfo {
+            name: f.hot_fn.name,
+            module_path: f.hot_fn.module_path,
+            sighash: f.hot_fn.sighash,
+            version: f.version,
+            last_reload: f.last_reload,
+            versions: f.versions(),
+        })
+        .collect();
+    fns.sort_by_key(|f| (f.module_path, f.name));
+    fns
+}
+/// Lists every hot-reloadable function in the app, sorted by module and name.
+///
+/// Generic functions are listed once for every instantiation that has been
+/// called, and methods once they have been called.
+#[cfg(not(feature = "unstub"))]
+pub fn functions() -> Vec<FunctionInfo> {
+    Vec::new()
+}
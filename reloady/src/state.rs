@@ -0,0 +1,240 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::{
+    marker::PhantomData,
+    ops::Deref,
+    ptr,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Mutex,
+    },
+};
+
+// the part of a hot state that every image agrees on, regardless of `T`.
+// reloaded images only ever touch the original process's header.
+#[repr(C)]
+struct StateHeader {
+    lock: Mutex<()>,
+    // Entry<T> for whichever T last initialized or migrated the state
+    entry: AtomicPtr<()>,
+}
+
+// published once and never mutated or freed, so readers can check the layout
+// without taking the lock
+#[repr(C)]
+struct Entry<T> {
+    layout: u64,
+    // of the whole entry, so it can be scanned without knowing `T`
+    size: usize,
+    value: T,
+}
+
+#[cfg(feature = "unstub")]
+lazy_static::lazy_static! {
+    // headers of the hot states in this process, by address
+    static ref __STATES: Mutex<Vec<(String, usize)>> = Mutex::new(Vec::new());
+}
+
+// a hot state header in the original process, which it or an image uses
+#[cfg(feature = "unstub")]
+pub(crate) fn register_state(name: &str, header: *const ()) {
+    let mut states = __STATES.lock().unwrap();
+    if !states.iter().any(|&(_, h)| h == header as usize) {
+        // hot states are exported as `__reloady_state::path::NAME`
+        let name = name.strip_prefix("__reloady_state::").unwrap_or(name);
+        states.push((name.to_string(), header as usize));
+    }
+}
+
+// hot states whose value holds a word that points into one of `ranges`, e.g.
+// a `&'static str` or vtable of an image about to be unloaded. only looks at
+// the value itself, not at what it points to
+#[cfg(feature = "unstub")]
+pub(crate) fn pointers_into(ranges: &[std::ops::Range<usize>]) -> Vec<String> {
+    let mut found = Vec::new();
+    for (name, header) in __STATES.lock().unwrap().iter() {
+        // SAFETY: registered headers are statics of the original process
+        let header = unsafe { &*(*header as *const StateHeader) };
+        let entry = header.entry.load(Ordering::Acquire) as *const u8;
+        if entry.is_null() {
+            continue;
+        }
+        // SAFETY: entries are never freed and start with a layout and size
+        let size = unsafe { (*(entry as *const Entry<()>)).size };
+        let word = std::mem::size_of::<usize>();
+        for offset in (0..size / word).map(|i| i * word) {
+            // SAFETY: within the entry. other threads may be writing it, which
+            // at worst misses a pointer
+            let value = unsafe { ptr::read_volatile(entry.add(offset) as *const usize) };
+            if ranges.iter().any(|r| r.contains(&value)) {
+                found.push(format!("hot state {} (at offset {})", name, offset));
+            }
+        }
+    }
+    found
+}
+
+/// A `static` whose value survives hot reloads.
+///
+/// Created by `#[reloady::hot_state]`. The original process owns the value;
+/// the same static in a reloaded image resolves to the original one, so every
+/// version of the code sees a single instance. The value is initialized on
+/// first access.
+///
+/// If the type's layout changes between reloads, the value is converted by the
+/// migration given with `#[reloady::hot_state(migrate = ...)]`.
+#[repr(C)]
+pub struct HotState<T: 'static> {
+    // must stay first, the host resolver hands out pointers to the whole static
+    header: StateHeader,
+    name: &'static str,
+    init: fn() -> T,
+    migration: Option<Migration<T>>,
+    // header actually in use, resolved once per image
+    resolved: AtomicPtr<StateHeader>,
+    _marker: PhantomData<T>,
+}
+
+/// Converts a hot state's value from an older layout, see `#[reloady::migrate]`.
+pub struct Migration<T> {
+    from_layout: fn() -> u64,
+    migrate: fn(*mut ()) -> T,
+}
+
+impl<T> Migration<T> {
+    #[doc(hidden)]
+    pub const fn new(from_layout: fn() -> u64, migrate: fn(*mut ()) -> T) -> Self {
+        Self {
+            from_layout,
+            migrate,
+        }
+    }
+}
+
+// hashes what the stored bytes depend on, so images agree on whether a
+// value can be reused as-is
+#[doc(hidden)]
+pub fn __layout_hash<T>() -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    std::mem::size_of::<T>().hash(&mut hasher);
+    std::mem::align_of::<T>().hash(&mut hasher);
+    std::mem::needs_drop::<T>().hash(&mut hasher);
+    hasher.finish()
+}
+
+// moves the value out of an entry written with an older layout; the entry
+// itself is leaked since old code may still hold references into it
+#[doc(hidden)]
+pub unsafe fn __take_state<T>(entry: *mut ()) -> T {
+    ptr::read(&(*(entry as *mut Entry<T>)).value)
+}
+
+impl<T> HotState<T> {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str, init: fn() -> T) -> Self {
+        Self::with_migration(name, init, None)
+    }
+
+    #[doc(hidden)]
+    pub const fn with_migration(
+        name: &'static str,
+        init: fn() -> T,
+        migration: Option<Migration<T>>,
+    ) -> Self {
+        Self {
+            header: StateHeader {
+                lock: Mutex::new(()),
+                entry: AtomicPtr::new(ptr::null_mut()),
+            },
+            name,
+            init,
+            migration,
+            resolved: AtomicPtr::new(ptr::null_mut()),
+            _marker: PhantomData,
+        }
+    }
+
+    fn header(&self) -> &StateHeader {
+        let mut header = self.resolved.load(Ordering::Acquire);
+        if header.is_null() {
+            header = crate::resolve_host_symbol(self.name) as *mut StateHeader;
+            if header.is_null() {
+                // new in this image (or we are the host), keep our own copy
+                header = &self.header as *const StateHeader as *mut StateHeader;
+                #[cfg(feature = "unstub")]
+                register_state(self.name, header as *const ());
+            }
+            self.resolved.store(header, Ordering::Release);
+        }
+        // SAFETY: points at this static or the host's copy of it, both live
+        // for the rest of the process
+        unsafe { &*header }
+    }
+
+    // slow path: first access, or first access since the layout changed
+    fn init_or_migrate(&self, header: &StateHeader, layout: u64) -> *mut Entry<T> {
+        let _guard = header.lock.lock().unwrap();
+        let entry = header.entry.load(Ordering::Acquire);
+        if entry.is_null() {
+            return self.publish(header, (self.init)(), layout);
+        }
+        // SAFETY: every entry starts with its layout hash
+        let current = unsafe { *(entry as *const u64) };
+        if current == layout {
+            return entry as *mut Entry<T>;
+        }
+        match self.migration {
+            Some(ref m) if (m.from_layout)() == current => {
+                let value = (m.migrate)(entry);
+                println!("migrated hot state {} to new layout", self.name);
+                self.publish(header, value, layout)
+            }
+            _ => panic!(
+                "ERR-PANIC: layout of hot state {} changed and no migration matches, please restart!",
+                self.name
+            ),
+        }
+    }
+
+    fn publish(&self, header: &StateHeader, value: T, layout: u64) -> *mut Entry<T> {
+        let entry = Box::into_raw(Box::new(Entry {
+            layout,
+            size: std::mem::size_of::<Entry<T>>(),
+            value,
+        }));
+        header.entry.store(entry as *mut (), Ordering::Release);
+        entry
+    }
+}
+
+impl<T> Deref for HotState<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        let header = self.header();
+        let layout = __layout_hash::<T>();
+        let mut entry = header.entry.load(Ordering::Acquire) as *mut Entry<T>;
+        // SAFETY: every entry starts with its layout hash
+        if entry.is_null() || unsafe { *(entry as *const u64) } != layout {
+            entry = self.init_or_migrate(header, layout);
+        }
+        // SAFETY: entries are leaked Boxes, never freed, and the layout matches T
+        unsafe { &(*entry).value }
+    }
+}
@@ -0,0 +1,227 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Unloading old images once none of their code can be running.
+//!
+//! An image is retired once no hot fn (or rollback history entry) points into
+//! it anymore. Calls that started before that may still be running its code,
+//! so every call to a hot fn counts itself in the slot of the epoch it
+//! started in. The epoch only advances once the calls of the epoch before it
+//! are done, so an image retired in epoch `r` can be unloaded once the epoch
+//! is `r + 2`.
+//!
+//! With the dangling check on, hot state is scanned for pointers into an
+//! image before unloading it, and the image is leaked instead if any are
+//! found.
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use libloading::Library;
+
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+// calls in flight, by the parity of the epoch they started in
+static ACTIVE: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+// whether retired images wait for calls to finish, so the end of a call
+// should try to unload them
+static PENDING: AtomicBool = AtomicBool::new(false);
+static NEVER_UNLOAD: AtomicBool = AtomicBool::new(false);
+static CHECK_DANGLING: AtomicBool = AtomicBool::new(false);
+
+struct OldImage {
+    version: usize,
+    // unloaded when dropped
+    _lib: Library,
+    // where the image is mapped, empty if unknown
+    ranges: Vec<Range<usize>>,
+    // epoch in which nothing pointed into the image anymore
+    retired: Option<usize>,
+}
+
+lazy_static::lazy_static! {
+    static ref __OLD_IMAGES: Mutex<Vec<OldImage>> = Mutex::new(Vec::new());
+    // where each loaded build is mapped, recorded while its file is still
+    // the one that was loaded
+    static ref __RANGES: Mutex<HashMap<usize, Vec<Range<usize>>>> = Mutex::new(HashMap::new());
+}
+
+// held for the duration of every call to a hot fn
+#[doc(hidden)]
+pub struct __CallGuard(usize);
+
+#[doc(hidden)]
+#[inline(always)]
+pub fn __enter_call() -> __CallGuard {
+    let slot = EPOCH.load(Ordering::SeqCst) % 2;
+    ACTIVE[slot].fetch_add(1, Ordering::SeqCst);
+    __CallGuard(slot)
+}
+
+impl Drop for __CallGuard {
+    #[inline(always)]
+    fn drop(&mut self) {
+        if ACTIVE[self.0].fetch_sub(1, Ordering::SeqCst) == 1 && PENDING.load(Ordering::Relaxed) {
+            collect();
+        }
+    }
+}
+
+pub(crate) fn set_never_unload(never: bool) {
+    NEVER_UNLOAD.store(never, Ordering::Relaxed);
+}
+
+pub(crate) fn set_check_dangling(check: bool) {
+    CHECK_DANGLING.store(check, Ordering::Relaxed);
+}
+
+// build `version` was just loaded from `path`
+pub(crate) fn loaded(version: usize, path: &str) {
+    __RANGES.lock().unwrap().insert(version, image_ranges(path));
+}
+
+// the image of build `version` was replaced as the current image
+pub(crate) fn retire(version: usize, lib: Library) {
+    let ranges = __RANGES
+        .lock()
+        .unwrap()
+        .remove(&version)
+        .unwrap_or_default();
+    __OLD_IMAGES.lock().unwrap().push(OldImage {
+        version,
+        _lib: lib,
+        ranges,
+        retired: None,
+    });
+}
+
+// unload every old image whose code can't be running anymore
+pub(crate) fn collect() {
+    if NEVER_UNLOAD.load(Ordering::Relaxed) {
+        return;
+    }
+    // may run at the end of any call, possibly while these are held
+    let mut images = match __OLD_IMAGES.try_lock() {
+        Ok(images) => images,
+        Err(_) => return,
+    };
+    let hot_fns = match crate::registry::__HOT_FNS.try_lock() {
+        Ok(hot_fns) => hot_fns,
+        Err(_) => return,
+    };
+    for image in images.iter_mut().filter(|i| i.retired.is_none()) {
+        if !hot_fns.values().any(|f| f.uses(image.version)) {
+            image.retired = Some(EPOCH.load(Ordering::SeqCst));
+        }
+    }
+    drop(hot_fns);
+    // every call from before the current epoch has to be done before the
+    // next one starts
+    for _ in 0..2 {
+        let epoch = EPOCH.load(Ordering::SeqCst);
+        if ACTIVE[(epoch + 1) % 2].load(Ordering::SeqCst) != 0 {
+            break;
+        }
+        let _ = EPOCH.compare_exchange(epoch, epoch + 1, Ordering::SeqCst, Ordering::SeqCst);
+    }
+    let epoch = EPOCH.load(Ordering::SeqCst);
+    let (unload, keep): (Vec<_>, Vec<_>) = images
+        .drain(..)
+        .partition(|image| matches!(image.retired, Some(retired) if retired + 2 <= epoch));
+    *images = keep;
+    for image in unload {
+        if dangling(&image) {
+            std::mem::forget(image);
+        } else {
+            eprintln!("info: unloading build {}", image.version);
+            crate::registry::forget_image(image.version);
+        }
+    }
+    let waiting = images.iter().any(|image| image.retired.is_some());
+    PENDING.store(waiting, Ordering::Relaxed);
+}
+
+// with the dangling check on, whether anything may still point into the
+// image, which then has to stay loaded
+fn dangling(image: &OldImage) -> bool {
+    if !CHECK_DANGLING.load(Ordering::Relaxed) {
+        return false;
+    }
+    if image.ranges.is_empty() {
+        eprintln!(
+            "warning: can't tell where build {} is mapped, keeping it loaded",
+            image.version
+        );
+        return true;
+    }
+    let found = crate::state::pointers_into(&image.ranges);
+    for place in &found {
+        eprintln!(
+            "warning: {} points into build {}, which is being unloaded, keeping it loaded",
+            place, image.version
+        );
+    }
+    !found.is_empty()
+}
+
+// the mappings of the file at `path`, and the zeroed memory right after them
+// (its .bss)
+#[cfg(target_os = "linux")]
+fn image_ranges(path: &str) -> Vec<Range<usize>> {
+    use std::os::unix::fs::MetadataExt;
+    // the same path is reused by later builds, but each is a new file
+    let inode = match std::fs::metadata(path) {
+        Ok(m) => m.ino(),
+        Err(_) => return Vec::new(),
+    };
+    let maps = match std::fs::read_to_string("/proc/self/maps") {
+        Ok(maps) => maps,
+        Err(_) => return Vec::new(),
+    };
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for line in maps.lines() {
+        // start-end perms offset dev inode [path]
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let range = match (
+            fields.first().and_then(|r| r.split_once('-')),
+            fields.get(4),
+        ) {
+            (Some((start, end)), Some(ino)) => match (
+                usize::from_str_radix(start, 16),
+                usize::from_str_radix(end, 16),
+                ino.parse::<u64>(),
+            ) {
+                (Ok(start), Ok(end), Ok(ino)) => (start..end, ino),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        let follows = ranges.last().is_some_and(|r| r.end == range.0.start);
+        if range.1 == inode || (range.1 == 0 && fields.len() == 5 && follows) {
+            ranges.push(range.0);
+        }
+    }
+    ranges
+}
+#[cfg(not(target_os = "linux"))]
+fn image_ranges(_: &str) -> Vec<Range<usize>> {
+    Vec::new()
+}
@@ -0,0 +1,88 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! `async fn`s return the same boxed future type in every build.
+
+use std::{
+    future::Future,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+#[reloady::hot_reload]
+async fn add(a: u32, b: &u32) -> u32 {
+    let a: &u32 = &a;
+    a + b
+}
+
+#[reloady::hot_reload]
+async fn longest<'a>(x: &'a str, y: &'a str) -> &'a str {
+    if x.len() >= y.len() {
+        x
+    } else {
+        y
+    }
+}
+
+#[reloady::hot_reload]
+async fn push_twice(v: &mut Vec<u32>, n: u32) {
+    v.push(n);
+    if v.len() > 2 {
+        return;
+    }
+    v.push(n);
+}
+
+#[reloady::hot_reload(?Send)]
+async fn not_send(n: Rc<u32>) -> u32 {
+    *n + 1
+}
+
+// just enough of an executor for futures that never wait
+fn block_on<F: Future>(f: F) -> F::Output {
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut f = Box::pin(f);
+    loop {
+        if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+            return v;
+        }
+    }
+}
+
+fn assert_send<T: Send>(t: T) -> T {
+    t
+}
+
+#[test]
+fn borrowed_args() {
+    let b = 2;
+    assert_eq!(block_on(assert_send(add(1, &b))), 3);
+    assert_eq!(block_on(longest("abc", "de")), "abc");
+}
+
+#[test]
+fn unit_return() {
+    let mut v = Vec::new();
+    block_on(push_twice(&mut v, 1));
+    block_on(push_twice(&mut v, 2));
+    assert_eq!(v, [1, 1, 2]);
+}
+
+#[test]
+fn local_future() {
+    assert_eq!(block_on(not_send(Rc::new(4))), 5);
+}
@@ -0,0 +1,75 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Every instantiation of a generic hot function is registered separately.
+#![feature(linkage)]
+
+use std::fmt::Debug;
+
+#[reloady::hot_reload]
+fn describe<T: Debug>(x: T) -> String {
+    format!("{:?}", x)
+}
+
+#[reloady::hot_reload]
+fn total<const N: usize>(xs: [u32; N]) -> u32 {
+    xs.iter().sum()
+}
+
+#[reloady::hot_reload]
+fn longest<'a, T: ?Sized + AsRef<str>>(a: &'a T, b: &'a T) -> &'a T {
+    if a.as_ref().len() >= b.as_ref().len() {
+        a
+    } else {
+        b
+    }
+}
+
+fn registered(name: &str) -> bool {
+    reloady::functions().iter().any(|f| f.name == name)
+}
+
+#[test]
+fn instantiations() {
+    assert_eq!(describe(1u8), "1");
+    assert_eq!(describe("a"), "\"a\"");
+    assert_eq!(describe(Some(2u8)), "Some(2)");
+    assert!(registered("describe::<u8>"));
+    assert!(registered("describe::<&str>"));
+    assert!(registered("describe::<core::option::Option<u8>>"));
+}
+
+#[test]
+fn const_generics() {
+    assert_eq!(total([1, 2, 3]), 6);
+    assert_eq!(total([4; 5]), 20);
+    assert!(registered("total::<3>"));
+    assert!(registered("total::<5>"));
+}
+
+#[test]
+fn unsized_params() {
+    assert_eq!(longest("ab", "c"), "ab");
+    assert!(registered("longest::<str>"));
+}
+
+#[test]
+fn not_listed_before_first_call() {
+    assert!(!reloady::functions().iter().any(|f| f.name.starts_with("describe::<i64>")));
+    describe(0i64);
+    assert!(registered("describe::<i64>"));
+}
@@ -0,0 +1,60 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Rolling back without any builds loaded.
+
+use reloady::RollbackError;
+
+#[reloady::hot_reload]
+fn double(x: u32) -> u32 {
+    x * 2
+}
+
+#[test]
+fn original_build() {
+    assert_eq!(reloady::rollback("double", 0), Ok(()));
+    assert_eq!(reloady::rollback("history::double", 0), Ok(()));
+    assert_eq!(double(4), 8);
+    let info = reloady::functions();
+    let info = info.iter().find(|f| f.name == "double").unwrap();
+    assert_eq!((info.version, &info.versions[..]), (0, &[0][..]));
+    assert_eq!(reloady::rollback_all(0), Ok(()));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        reloady::rollback("triple", 0),
+        Err(RollbackError::UnknownFunction("triple".to_string()))
+    );
+    assert_eq!(
+        reloady::rollback("double", 1),
+        Err(RollbackError::UnknownBuild(1))
+    );
+    assert_eq!(
+        reloady::rollback_all(3),
+        Err(RollbackError::UnknownBuild(3))
+    );
+    assert_eq!(
+        RollbackError::Unavailable {
+            name: "double".to_string(),
+            version: 2
+        }
+        .to_string(),
+        "no version of double from build 2 to roll back to"
+    );
+}
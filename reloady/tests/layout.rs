@@ -0,0 +1,131 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Layout fingerprints of the types hot functions use.
+
+use reloady::{LayoutChange, TypeLayout};
+
+#[derive(reloady::Layout)]
+pub struct Point {
+    x: f32,
+    y: f32,
+}
+
+// same size and fields as Point, but in a different order
+#[derive(reloady::Layout)]
+pub struct Swapped {
+    y: f32,
+    x: f32,
+}
+
+#[derive(reloady::Layout)]
+pub struct Tagged(u32, Point);
+
+#[derive(reloady::Layout)]
+pub enum Shape {
+    Dot(Point),
+    Line { from: Point, to: Point },
+}
+
+#[derive(reloady::Layout)]
+pub struct Wrap<T> {
+    inner: T,
+}
+
+#[reloady::hot_reload]
+fn shift(shape: &Shape, by: (Point, u8)) -> Tagged {
+    match shape {
+        Shape::Dot(p) | Shape::Line { from: p, .. } => Tagged(
+            by.1 as u32,
+            Point {
+                x: p.x + by.0.x,
+                y: p.y + by.0.y,
+            },
+        ),
+    }
+}
+
+fn layouts(f: fn(&mut dyn FnMut(TypeLayout))) -> Vec<TypeLayout> {
+    let mut layouts = Vec::new();
+    f(&mut |layout| layouts.push(layout));
+    layouts
+}
+
+#[test]
+fn derived() {
+    let point = reloady::__layout_of!(Point);
+    assert_eq!(point.kind, "struct");
+    assert_eq!((point.size, point.align), (8, 4));
+    assert_ne!(point.fields, reloady::__layout_of!(Swapped).fields);
+    assert_eq!(reloady::__layout_of!(Tagged).kind, "struct");
+    assert_eq!(reloady::__layout_of!(Shape).kind, "enum");
+    assert_ne!(
+        reloady::__layout_of!(Wrap<u32>).fields,
+        reloady::__layout_of!(Wrap<f32>).fields
+    );
+    // only size and alignment for types without the derive
+    let plain = reloady::__layout_of!(u64);
+    assert_eq!((plain.kind, plain.size, plain.fields), ("type", 8, 0));
+}
+
+#[test]
+fn reachable_types() {
+    let names: Vec<_> = layouts(__shift_fn_impl__reloady_layouts)
+        .iter()
+        .map(|l| l.name)
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "layout::Shape",
+            "(layout::Point, u8)",
+            "layout::Point",
+            "u8",
+            "layout::Tagged"
+        ]
+    );
+    let shape = reloady::__layout_of!(Shape);
+    assert_eq!(
+        shift(
+            &Shape::Dot(Point { x: 1., y: 2. }),
+            (Point { x: 1., y: 1. }, 3)
+        )
+        .1
+        .y,
+        3.
+    );
+    assert_eq!(layouts(__shift_fn_impl__reloady_layouts)[0], shape);
+}
+
+#[test]
+fn change_message() {
+    let change = LayoutChange {
+        kind: "struct".to_string(),
+        name: "layout::Point".to_string(),
+        expected: (8, 4),
+        found: (12, 4),
+    };
+    assert_eq!(
+        change.to_string(),
+        "struct `layout::Point` changed layout (size 8 -> 12, align 4 -> 4)"
+    );
+    let change = LayoutChange {
+        found: (8, 4),
+        ..change
+    };
+    assert_eq!(change.to_string(), "struct `layout::Point` changed layout");
+}
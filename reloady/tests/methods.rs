@@ -0,0 +1,148 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Methods, whole impl blocks and trait impls can be hot functions.
+#![cfg_attr(feature = "nightly", feature(linkage))]
+#![allow(clippy::boxed_local)]
+
+pub struct Counter {
+    n: u32,
+}
+
+impl Counter {
+    #[reloady::hot_reload]
+    fn new(n: u32) -> Self {
+        Self { n }
+    }
+
+    #[reloady::hot_reload]
+    fn get(&self) -> u32 {
+        self.n
+    }
+
+    #[reloady::hot_reload]
+    fn bump(&mut self, by: u32) -> &mut Self {
+        self.n += by;
+        self
+    }
+
+    #[reloady::hot_reload]
+    fn into_boxed(self: Box<Self>) -> u32 {
+        self.n
+    }
+}
+
+#[cfg(feature = "nightly")]
+pub struct Wrapper<T>(T);
+
+#[cfg(feature = "nightly")]
+#[reloady::hot_reload]
+impl<T: Clone> Wrapper<T> {
+    fn get(&self) -> T {
+        self.0.clone()
+    }
+
+    fn map<U, F: FnOnce(T) -> U>(self, f: F) -> Wrapper<U> {
+        Wrapper(f(self.0))
+    }
+
+    // const fns are left alone
+    const fn inner(&self) -> &T {
+        &self.0
+    }
+}
+
+pub trait Shape {
+    type Side;
+    const SIDES: u32;
+
+    fn perimeter(&self) -> Self::Side;
+}
+
+pub struct Square(u32);
+
+#[reloady::hot_reload]
+impl Shape for Square {
+    type Side = u32;
+    const SIDES: u32 = 4;
+
+    fn perimeter(&self) -> Self::Side {
+        self.0 * Self::SIDES
+    }
+}
+
+pub trait Convert<T> {
+    fn convert(&self) -> T;
+}
+
+#[reloady::hot_reload]
+impl Convert<u64> for Square {
+    fn convert(&self) -> u64 {
+        self.0 as u64
+    }
+}
+
+#[reloady::hot_reload]
+impl Convert<String> for Square {
+    fn convert(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+fn registered(name: &str) -> bool {
+    reloady::functions().iter().any(|f| f.name == name)
+}
+
+#[test]
+fn receivers() {
+    let mut c = Counter::new(1);
+    c.bump(2).bump(3);
+    assert_eq!(c.get(), 6);
+    assert_eq!(Box::new(c).into_boxed(), 6);
+    assert!(registered("Counter::new"));
+    assert!(registered("Counter::bump"));
+    assert!(registered("Counter::into_boxed"));
+}
+
+// generic methods are only hot with the `nightly` feature
+#[cfg(feature = "nightly")]
+#[test]
+fn generic_impls() {
+    let w = Wrapper(2u8);
+    assert_eq!(w.get(), 2);
+    assert_eq!(*w.inner(), 2);
+    assert_eq!(w.map(|x| x.to_string()).get(), "2");
+    assert!(registered("Wrapper<u8>::get"));
+    assert!(reloady::functions().iter().any(|f| f
+        .name
+        .starts_with("Wrapper<u8>::map::<alloc::string::String, ")));
+    assert!(registered("Wrapper<alloc::string::String>::get"));
+    assert!(!reloady::functions()
+        .iter()
+        .any(|f| f.name.ends_with("::inner")));
+}
+
+#[test]
+fn trait_impls() {
+    let s = Square(3);
+    assert_eq!(s.perimeter(), 12);
+    assert_eq!(Convert::<u64>::convert(&s), 3);
+    assert_eq!(Convert::<String>::convert(&s), "3");
+    assert!(registered("<Square as Shape>::perimeter"));
+    assert!(registered("<Square as Convert<u64>>::convert"));
+    assert!(registered("<Square as Convert<String>>::convert"));
+}
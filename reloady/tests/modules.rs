@@ -0,0 +1,103 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! `#[hot_reload]` on a module makes everything in it hot.
+#![feature(custom_inner_attributes)]
+
+#[reloady::hot_reload]
+mod systems {
+    pub fn tick(n: u32) -> u32 {
+        n + 1
+    }
+
+    // can't be swapped, stays a plain const fn
+    pub const fn limit() -> u32 {
+        10
+    }
+
+    #[reloady::hot_reload(skip)]
+    pub fn cold(x: impl Into<u32>) -> u32 {
+        x.into()
+    }
+
+    pub struct Body(pub u32);
+
+    impl Body {
+        pub fn mass(&self) -> u32 {
+            self.0
+        }
+
+        #[reloady::hot_reload(skip)]
+        pub fn density(&self) -> u32 {
+            1
+        }
+    }
+
+    pub mod nested {
+        pub fn deep() -> &'static str {
+            "deep"
+        }
+    }
+
+    #[reloady::hot_reload(?Send)]
+    pub mod local {
+        pub async fn shared() -> std::rc::Rc<u32> {
+            std::rc::Rc::new(1)
+        }
+    }
+}
+
+mod inner {
+    #![reloady::hot_reload]
+
+    pub fn five() -> u32 {
+        5
+    }
+}
+
+fn registered(name: &str) -> bool {
+    reloady::functions().iter().any(|f| f.name == name)
+}
+
+#[test]
+fn free_fns() {
+    assert_eq!(systems::tick(1), 2);
+    assert_eq!(systems::nested::deep(), "deep");
+    assert_eq!(inner::five(), 5);
+    assert!(registered("tick"));
+    assert!(registered("deep"));
+    assert!(registered("shared"));
+    assert!(registered("five"));
+}
+
+#[test]
+fn skipped() {
+    const LIMIT: u32 = systems::limit();
+    assert_eq!(LIMIT, 10);
+    assert_eq!(systems::cold(3u8), 3);
+    assert!(!registered("limit"));
+    assert!(!registered("cold"));
+}
+
+#[test]
+fn methods() {
+    let body = systems::Body(3);
+    assert_eq!(body.mass(), 3);
+    assert_eq!(body.density(), 1);
+    assert!(registered("Body::mass"));
+    assert!(!registered("Body::density"));
+}
@@ -0,0 +1,147 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Hot functions can destructure their arguments like any other function.
+#![cfg_attr(feature = "nightly", feature(linkage))]
+#![allow(clippy::toplevel_ref_arg)]
+
+use std::{
+    future::Future,
+    task::{Context, Poll, Waker},
+};
+
+#[derive(Clone, Copy)]
+pub struct Point {
+    x: i32,
+    y: i32,
+}
+
+pub struct Meters(f64);
+
+#[reloady::hot_reload]
+fn tuple((a, b): (u32, u32)) -> u32 {
+    a + b
+}
+
+#[reloady::hot_reload]
+fn structs(Point { x, y }: Point) -> i32 {
+    x * y
+}
+
+#[reloady::hot_reload]
+fn tuple_struct(Meters(m): Meters) -> f64 {
+    m * 2.0
+}
+
+#[reloady::hot_reload]
+fn array([first, .., last]: [u8; 4]) -> u8 {
+    first + last
+}
+
+#[reloady::hot_reload]
+fn slice(&[a, b]: &[u8; 2]) -> u8 {
+    a * b
+}
+
+#[reloady::hot_reload]
+fn nested((Point { x, .. }, (_, c)): (Point, (u8, u8))) -> i32 {
+    x + c as i32
+}
+
+#[reloady::hot_reload]
+fn by_ref(ref s: String, mut n: usize) -> usize {
+    n += s.len();
+    n
+}
+
+#[reloady::hot_reload]
+fn binding(all @ (a, _): (u8, u8)) -> (u8, u8) {
+    (a, all.1)
+}
+
+#[reloady::hot_reload]
+fn wildcard(_: u32, x: u32) -> u32 {
+    x
+}
+
+#[reloady::hot_reload]
+async fn async_tuple((a, b): (String, String)) -> String {
+    a + &b
+}
+
+#[reloady::hot_reload]
+fn generic<T: Clone>((a, _): (T, T)) -> T {
+    a
+}
+
+#[reloady::hot_reload]
+impl Point {
+    fn offset(&self, (dx, dy): (i32, i32)) -> Self {
+        Point {
+            x: self.x + dx,
+            y: self.y + dy,
+        }
+    }
+
+    fn dot(self, Point { x, y }: Point) -> i32 {
+        self.x * x + self.y * y
+    }
+}
+
+#[test]
+fn destructuring() {
+    let p = Point { x: 2, y: 3 };
+    assert_eq!(tuple((1, 2)), 3);
+    assert_eq!(structs(p), 6);
+    assert_eq!(tuple_struct(Meters(1.5)), 3.0);
+    assert_eq!(array([1, 2, 3, 4]), 5);
+    assert_eq!(slice(&[3, 4]), 12);
+    assert_eq!(nested((p, (7, 8))), 10);
+    assert_eq!(binding((1, 2)), (1, 2));
+    assert_eq!(generic(("a", "b")), "a");
+}
+
+#[test]
+fn bindings() {
+    assert_eq!(by_ref("abc".to_string(), 1), 4);
+    assert_eq!(wildcard(1, 2), 2);
+}
+
+#[test]
+fn async_patterns() {
+    let future = async_tuple(("a".to_string(), "b".to_string()));
+    assert_eq!(block_on(future), "ab");
+}
+
+#[test]
+fn methods() {
+    let p = Point { x: 1, y: 2 };
+    let q = p.offset((1, 1));
+    assert_eq!((q.x, q.y), (2, 3));
+    assert_eq!(p.dot(q), 8);
+}
+
+// just enough of an executor for futures that never wait
+fn block_on<F: Future>(f: F) -> F::Output {
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut f = Box::pin(f);
+    loop {
+        if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+            return v;
+        }
+    }
+}
@@ -0,0 +1,90 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Hot functions calling themselves and each other, from many threads.
+
+use std::sync::{Arc, Barrier};
+
+#[reloady::hot_reload]
+fn factorial(n: u64) -> u64 {
+    if n == 0 {
+        1
+    } else {
+        n * factorial(n - 1)
+    }
+}
+
+#[reloady::hot_reload]
+fn is_even(n: u32) -> bool {
+    n == 0 || is_odd(n - 1)
+}
+
+#[reloady::hot_reload]
+fn is_odd(n: u32) -> bool {
+    n != 0 && is_even(n - 1)
+}
+
+// calls back into a hot fn through a closure
+#[reloady::hot_reload]
+fn apply(f: &dyn Fn(u64) -> u64, n: u64) -> u64 {
+    f(n)
+}
+
+#[test]
+fn recursion() {
+    assert_eq!(factorial(20), 2_432_902_008_176_640_000);
+}
+
+#[test]
+fn mutual_recursion() {
+    assert!(is_even(1000));
+    assert!(is_odd(777));
+}
+
+#[test]
+fn reentrant_through_closure() {
+    assert_eq!(apply(&|n| apply(&factorial, n) + 1, 5), 121);
+}
+
+#[test]
+fn parallel_callers() {
+    let threads = 8;
+    let barrier = Arc::new(Barrier::new(threads));
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let barrier = barrier.clone();
+            std::thread::spawn(move || {
+                barrier.wait();
+                for i in 0..10_000 {
+                    assert_eq!(is_even(i % 100), i % 2 == 0);
+                    assert_eq!(factorial(10), 3_628_800);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn registered() {
+    let names: Vec<_> = reloady::functions().iter().map(|f| f.name).collect();
+    for name in &["apply", "factorial", "is_even", "is_odd"] {
+        assert!(names.contains(name), "{} is not registered", name);
+    }
+}
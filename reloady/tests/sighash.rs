@@ -0,0 +1,87 @@
+/*
+ * reloady - Simple, performant hot-reloading for Rust.
+ * Copyright (C) 2021 the reloady authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published
+ * by the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Signature hashes only depend on what the signature means.
+
+#[reloady::hot_reload]
+fn add(a: u32, b: u32) -> u32 {
+    a + b
+}
+
+#[reloady::hot_reload]
+fn add_renamed(left: u32, (right,): (u32,)) -> u32 {
+    left + right
+}
+
+#[reloady::hot_reload]
+#[rustfmt::skip]
+fn add_reformatted( x :u32,y:u32 )->u32 {
+    x + y
+}
+
+#[reloady::hot_reload]
+fn add_u64(a: u64, b: u64) -> u64 {
+    a + b
+}
+
+#[repr(C)]
+pub struct Pair {
+    a: u16,
+    b: u32,
+}
+
+#[reloady::hot_reload(deep)]
+fn sum(pair: &Pair, extra: Pair) -> u64 {
+    (pair.a + extra.a) as u64 + (pair.b + extra.b) as u64
+}
+
+#[reloady::hot_reload]
+fn sum_shallow(pair: &Pair, extra: Pair) -> u64 {
+    (pair.a + extra.a) as u64 + (pair.b + extra.b) as u64
+}
+
+fn sighash(name: &str) -> u64 {
+    reloady::functions()
+        .iter()
+        .find(|f| f.name == name)
+        .unwrap()
+        .sighash
+}
+
+#[test]
+fn ignores_arg_names_and_formatting() {
+    assert_eq!(sighash("add"), sighash("add_reformatted"));
+    assert_ne!(sighash("add"), sighash("add_renamed"));
+    assert_ne!(sighash("add"), sighash("add_u64"));
+}
+
+#[test]
+fn stable() {
+    // FNV-1a of `fn (u32, u32) -> u32`, the same for every compiler
+    assert_eq!(sighash("add"), 2770535884664488314);
+}
+
+#[test]
+fn deep() {
+    let layouts = [(8, 8), (8, 4), (8, 8)];
+    assert_eq!(
+        sighash("sum"),
+        reloady::__deep_sighash(sighash("sum_shallow"), &layouts)
+    );
+    let pair = Pair { a: 1, b: 2 };
+    assert_eq!(sum(&pair, Pair { a: 3, b: 4 }), 10);
+}